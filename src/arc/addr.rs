@@ -10,6 +10,10 @@ impl<T: ?Sized> ArcAddr<T> {
 	pub fn new(ptr: Arc<T>) -> Self {
 		ArcAddr { ptr }
 	}
+
+	fn addr(&self) -> *const () {
+		Arc::as_ptr(&self.ptr) as *const ()
+	}
 }
 
 impl<T: ?Sized> Deref for ArcAddr<T> {
@@ -21,7 +25,7 @@ impl<T: ?Sized> Deref for ArcAddr<T> {
 
 impl<T: ?Sized> PartialEq for ArcAddr<T> {
 	fn eq(&self, other: &Self) -> bool {
-		std::ptr::addr_eq(&self.ptr, &other.ptr)
+		self.addr() == other.addr()
 	}
 }
 
@@ -29,13 +33,13 @@ impl<T: ?Sized> Eq for ArcAddr<T> {}
 
 impl<T: ?Sized> Ord for ArcAddr<T> {
 	fn cmp(&self, other: &Self) -> Ordering {
-		Arc::as_ptr(&self.ptr).cmp(&Arc::as_ptr(&other.ptr))
+		self.addr().cmp(&other.addr())
 	}
 }
 
 impl<T: ?Sized> PartialOrd for ArcAddr<T> {
 	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-		Some(Arc::as_ptr(&self.ptr).cmp(&Arc::as_ptr(&other.ptr)))
+		Some(self.cmp(other))
 	}
 }
 
@@ -47,6 +51,10 @@ impl<T: ?Sized> WeakAddr<T> {
 	pub fn new(ptr: Weak<T>) -> Self {
 		WeakAddr { ptr }
 	}
+
+	fn addr(&self) -> *const () {
+		Weak::as_ptr(&self.ptr) as *const ()
+	}
 }
 
 impl<T: ?Sized> Deref for WeakAddr<T> {
@@ -58,7 +66,7 @@ impl<T: ?Sized> Deref for WeakAddr<T> {
 
 impl<T: ?Sized> PartialEq for WeakAddr<T> {
 	fn eq(&self, other: &Self) -> bool {
-		std::ptr::addr_eq(&self.ptr, &other.ptr)
+		self.addr() == other.addr()
 	}
 }
 
@@ -66,12 +74,12 @@ impl<T: ?Sized> Eq for WeakAddr<T> {}
 
 impl<T: ?Sized> Ord for WeakAddr<T> {
 	fn cmp(&self, other: &Self) -> Ordering {
-		Weak::as_ptr(&self.ptr).cmp(&Weak::as_ptr(&other.ptr))
+		self.addr().cmp(&other.addr())
 	}
 }
 
 impl<T: ?Sized> PartialOrd for WeakAddr<T> {
 	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-		Some(Weak::as_ptr(&self.ptr).cmp(&Weak::as_ptr(&other.ptr)))
+		Some(self.cmp(other))
 	}
 }
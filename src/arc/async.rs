@@ -1,30 +1,37 @@
 use std::any::Any;
-use std::collections::BTreeSet;
+use std::convert::Infallible;
 use std::hash::Hash;
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
-use arc_swap::ArcSwap;
 use futures::future::BoxFuture;
-use parking_lot::Mutex;
-use tokio::task::AbortHandle;
+use futures::FutureExt;
+use parking_lot::{MappedRwLockReadGuard, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 
-use crate::arc::addr::WeakAddr;
-use crate::arc::dependencies::Dependencies;
-use crate::arc::{Derived, Evaluation, Invalid, Observable, State, Version};
-use crate::hashed::Hashed;
+use crate::arc::node::{AsyncNode, Fingerprint, Job, Keyed, Node};
+use crate::arc::value::{Access, Value};
+use crate::arc::{Derived, Evaluation};
+use crate::async_state::{AsyncState, Attempt, NotReady};
+use crate::derivation::{self, Kind};
+use crate::pace::Pacer;
+use crate::retry::Retry;
+use crate::spawner::Spawner;
 
 #[doc(hidden)]
-pub struct Async<T>
+pub struct Async<T, E = Infallible>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	body: Arc<AsyncBody<T>>,
+	body: Arc<AsyncBody<T, E>>,
 }
 
-impl<T> Clone for Async<T>
+impl<T, E> Clone for Async<T, E>
 where
 	T: Send + Sync + Hash,
+	E: Send + Sync,
 {
 	fn clone(&self) -> Self {
 		Self {
@@ -33,295 +40,851 @@ where
 	}
 }
 
-impl<T: Send + Sync + Hash + 'static> From<Async<T>> for Arc<dyn Any> {
-	fn from(var: Async<T>) -> Self {
+impl<T: Send + Sync + Hash + 'static, E: Send + Sync + 'static> From<Async<T, E>> for Arc<dyn Any> {
+	fn from(var: Async<T, E>) -> Self {
 		var.body
 	}
 }
 
-impl<T: Send + Sync + Hash + 'static> TryFrom<Arc<dyn Any + Send + Sync>> for Async<T> {
+impl<T: Send + Sync + Hash + 'static, E: Send + Sync + 'static> TryFrom<Arc<dyn Any + Send + Sync>>
+	for Async<T, E>
+{
 	type Error = Arc<dyn Any + Send + Sync>;
 	fn try_from(value: Arc<dyn Any + Send + Sync>) -> Result<Self, Self::Error> {
-		Arc::downcast::<AsyncBody<T>>(value).map(|body| Async { body })
+		Arc::downcast::<AsyncBody<T, E>>(value).map(|body| Async { body })
 	}
 }
 
-pub struct AsyncBody<T>
+pub struct AsyncBody<T, E>
 where
 	T: Send + Hash + Sync + 'static,
+	E: Send + Sync + 'static,
 {
-	value: ArcSwap<Option<Hashed<T>>>,
-	inner: Mutex<AsyncInner<T>>,
+	value: RwLock<AsyncState<T, E>>,
+	inner: Mutex<AsyncInner<T, E>>,
 }
 
-struct AsyncEffect<
-	K: Hash + Send,
-	T,
-	H: Fn(&Evaluation) -> K + Send + 'static,
-	F: Fn(K, CancellationToken) -> BoxFuture<'static, T> + Send + 'static,
-> {
-	handler: H,
+struct AsyncEffect<K, H, F> {
+	keyed: Keyed<K, H>,
 	func: F,
-	value: Option<Hashed<K>>,
 }
 
-pub trait AsyncEffecty<T>: Send {
+pub trait AsyncEffecty<T, E>: Send {
 	fn compute(&mut self, cx: &Evaluation) -> u64;
-	fn invoke(&mut self, cancel: CancellationToken) -> BoxFuture<'static, T>;
+	fn invoke(&mut self, cancel: CancellationToken) -> BoxFuture<'static, Result<T, E>>;
 }
 
-impl<K, T, H, F> AsyncEffecty<T> for AsyncEffect<K, T, H, F>
+impl<K, T, E, H, F> AsyncEffecty<T, E> for AsyncEffect<K, H, F>
 where
 	K: Hash + Send,
 	H: Fn(&Evaluation) -> K + 'static + Send,
-	F: Fn(K, CancellationToken) -> BoxFuture<'static, T> + 'static + Send,
+	F: Fn(&K, CancellationToken) -> BoxFuture<'static, Result<T, E>> + 'static + Send,
 {
 	fn compute(&mut self, cx: &Evaluation) -> u64 {
-		let value = Hashed::new((self.handler)(cx));
-		self.value = Some(value);
-		return self.value.as_ref().unwrap().hash;
+		self.keyed.compute(cx)
 	}
 
-	fn invoke(&mut self, cancel: CancellationToken) -> BoxFuture<'static, T> {
-		(self.func)(self.value.take().unwrap().value, cancel)
+	fn invoke(&mut self, cancel: CancellationToken) -> BoxFuture<'static, Result<T, E>> {
+		(self.func)(self.keyed.key(), cancel)
 	}
 }
 
-pub struct AsyncInner<T>
+pub struct AsyncInner<T, E>
 where
 	T: Send + Hash + Sync + 'static,
+	E: Send + Sync + 'static,
 {
-	effect: Box<dyn AsyncEffecty<T>>,
-	future: Option<BoxFuture<'static, T>>,
-	revision: u64,
-	cancel: CancellationToken,
-	handle: Option<AbortHandle>,
-	state: State,
-	used_by: BTreeSet<WeakAddr<dyn Derived>>,
-	dependencies: Dependencies,
-	this: Weak<AsyncBody<T>>,
+	effect: Box<dyn AsyncEffecty<T, E>>,
+	revision: Option<u64>,
+	fingerprint: Fingerprint,
+	job: Job,
+	pacer: Pacer,
+	retry: Option<Retry<E>>,
+	attempt: u32,
+	refetch_interval: Option<Duration>,
+	stale: bool,
+	node: Node,
+	this: Weak<AsyncBody<T, E>>,
 }
 
-impl<T> Drop for AsyncInner<T>
+impl<T> Async<T>
 where
 	T: Send + Sync + Hash + 'static,
 {
-	fn drop(&mut self) {
-		let refr = self.this.clone() as Weak<dyn Derived>;
-		self.dependencies.drop(&refr);
+	pub fn new<K: Hash + Send + 'static>(
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(&K, CancellationToken) -> BoxFuture<'static, T> + 'static + Send,
+	) -> Self {
+		Async::new_with_name("<unnamed>", handler, func)
+	}
+
+	/// Same as [`Async::new`], named in the reports of its `Var` writes.
+	pub fn new_with_name<K: Hash + Send + 'static>(
+		name: &'static str,
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(&K, CancellationToken) -> BoxFuture<'static, T> + 'static + Send,
+	) -> Self {
+		Async::new_fallible_with_name(name, handler, move |key, cancel| {
+			func(key, cancel).map(Ok).boxed()
+		})
 	}
 }
 
-impl<T> Async<T>
+impl<T, E> Async<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	pub fn new<K: Hash + Send + 'static>(
+	/// Same as [`Async::new`], but the future can fail. A failure is
+	/// reported as [`AsyncState::Failed`] and keeps the last good value.
+	pub fn new_fallible<K: Hash + Send + 'static>(
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(&K, CancellationToken) -> BoxFuture<'static, Result<T, E>> + 'static + Send,
+	) -> Self {
+		Async::new_fallible_with_name("<unnamed>", handler, func)
+	}
+
+	/// Same as [`Async::new_fallible`], named in the reports of its `Var` writes.
+	pub fn new_fallible_with_name<K: Hash + Send + 'static>(
+		name: &'static str,
 		handler: impl Fn(&Evaluation) -> K + 'static + Send,
-		func: impl Fn(K, CancellationToken) -> BoxFuture<'static, T> + 'static + Send,
+		func: impl Fn(&K, CancellationToken) -> BoxFuture<'static, Result<T, E>> + 'static + Send,
 	) -> Self {
 		Async {
 			body: Arc::new_cyclic(|this| AsyncBody {
-				value: ArcSwap::new(Arc::new(None)),
+				value: RwLock::new(AsyncState::Pending),
 				inner: Mutex::new(AsyncInner {
 					effect: Box::new(AsyncEffect {
+						keyed: Keyed::new(handler),
 						func,
-						handler,
-						value: None,
-					}) as Box<dyn AsyncEffecty<T>>,
-					revision: 0,
-					future: None,
-					handle: None,
-					cancel: CancellationToken::new(),
-					state: State::Invalid(Invalid::Definitely),
-					used_by: BTreeSet::new(),
-					dependencies: Dependencies::new(),
+					}) as Box<dyn AsyncEffecty<T, E>>,
+					revision: None,
+					fingerprint: Fingerprint::new::<T, E>(),
+					job: Job::default(),
+					pacer: Pacer::default(),
+					retry: None,
+					attempt: 0,
+					refetch_interval: None,
+					stale: false,
+					node: Node::new(name, this.clone() as Weak<dyn Derived>),
 					this: this.clone(),
 				}),
 			}),
 		}
 	}
 
-	// #[inline]
-	// pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
-	// 	self.body.get_once()
-	// }
+	/// Runs futures of this value with `spawner` instead of
+	/// the [default](crate::spawner::default) one.
+	pub fn with_spawner(self, spawner: impl Spawner + 'static) -> Self {
+		self.body.inner.lock().job.set_spawner(Arc::new(spawner));
+		self
+	}
+
+	/// Starts a run only once the inputs stayed the same for `duration`.
+	/// The inputs are still tracked right away, the state shows loading.
+	/// Waiting uses tokio time, so runs need the tokio timer.
+	pub fn with_debounce(self, duration: Duration) -> Self {
+		self.body.inner.lock().pacer.debounce(duration);
+		self
+	}
+
+	/// Starts at most one run per `duration`, with the latest inputs.
+	/// Waiting uses tokio time, so runs need the tokio timer.
+	pub fn with_throttle(self, duration: Duration) -> Self {
+		self.body.inner.lock().pacer.throttle(duration);
+		self
+	}
+
+	/// Runs the future again when it fails, as long as `retry` allows.
+	/// Scheduled retries are reported as [`AsyncState::Retrying`].
+	pub fn with_retry(self, retry: Retry<E>) -> Self {
+		self.body.inner.lock().retry = Some(retry);
+		self
+	}
+
+	/// Runs the future again every `interval` after it finished, while
+	/// the value is observed. An unobserved value is refetched on the
+	/// next tracked read. Waiting uses tokio time, so refetches need the tokio timer.
+	pub fn with_refetch_interval(self, interval: Duration) -> Self {
+		self.body.inner.lock().refetch_interval = Some(interval);
+		self
+	}
+
+	/// Starts a new run with the current inputs, the running one is cancelled.
+	#[inline]
+	pub fn refresh(&self) {
+		self.body.refresh()
+	}
+
+	#[inline]
+	pub fn state_once(&self) -> MappedRwLockReadGuard<'_, AsyncState<T, E>> {
+		self.body.state_once()
+	}
+
+	#[inline]
+	pub fn state<'a>(
+		&'a self,
+		cx: &'a impl AsRef<Evaluation>,
+	) -> MappedRwLockReadGuard<'a, AsyncState<T, E>> {
+		self.body.state(cx.as_ref())
+	}
+
+	/// Same as [`Async::get`], but fails with [`NotReady`] while there is
+	/// no value, so derivations can short-circuit with `?`. A first run
+	/// that failed is reported with its error instead of as pending.
+	#[inline]
+	pub fn read(&self, cx: &impl AsRef<Evaluation>) -> Result<T, NotReady<E>>
+	where
+		T: Clone,
+		E: Clone,
+	{
+		self.body.state(cx.as_ref()).read().cloned()
+	}
 
+	/// The latest known value, fresh or stale, without tracking.
 	#[inline]
-	pub fn get<'a>(&'a self, cx: &'a impl AsRef<Evaluation>) -> u64 {
-		self.body.get(cx.as_ref())
+	pub fn get_once(&self) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.body.state_once().value().cloned()
+	}
+
+	/// The latest known value, fresh or stale.
+	#[inline]
+	pub fn get(&self, cx: &impl AsRef<Evaluation>) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.body.state(cx.as_ref()).value().cloned()
 	}
 }
 
-impl<T> AsyncBody<T>
+impl<T, E> AsyncBody<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	// pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
-	// 	self.update();
-	// 	MappedRwLockReadGuard::map(
-	// 		RwLockReadGuard::map(self.value.read(), |s| s.as_ref().unwrap()),
-	// 		|s| &s.value,
-	// 	)
-	// }
-
-	pub fn get<'a>(&'a self, eval: &'_ Evaluation) -> u64 {
+	pub fn state_once(&self) -> MappedRwLockReadGuard<'_, AsyncState<T, E>> {
+		self.inner_update(&mut self.inner.lock());
+		RwLockReadGuard::map(self.value.read(), |v| v)
+	}
+
+	pub fn state(&self, eval: &'_ Evaluation) -> MappedRwLockReadGuard<'_, AsyncState<T, E>> {
 		{
 			let mut self_mut = self.inner.lock();
 			self.inner_update(&mut self_mut);
-			eval.based_on(
-				self_mut.this.upgrade().unwrap(),
-				Version::Hash(self_mut.revision),
-			);
-			self_mut.used_by(eval.parent());
-			self_mut.revision
+			if self_mut.stale {
+				self.restart(&mut self_mut, None);
+			}
+
+			let this = self_mut.this.upgrade().unwrap();
+			let version = self_mut.fingerprint.version();
+			self_mut.node.track(this, version, eval);
+		}
+
+		RwLockReadGuard::map(self.value.read(), |v| v)
+	}
+
+	pub fn refresh(&self) {
+		let mut inner = self.inner.lock();
+		let cancel = inner.job.token();
+		self.inner_update(&mut inner);
+
+		// new inputs already started a fresh run
+		if cancel.is_cancelled() {
+			return;
+		}
+
+		if self.restart(&mut inner, None) {
+			Self::notify(inner);
 		}
 	}
 
-	pub(crate) fn used_by(&self, observable: Weak<dyn Derived>) {
-		self.inner.lock().used_by(observable);
+	/// Starts a new run for the current key, the previous one becomes stale.
+	/// Returns whether the state changed.
+	fn restart(&self, inner: &mut AsyncInner<T, E>, start: Option<Instant>) -> bool {
+		inner.job.restart();
+		let changed = self.transition(inner, AsyncState::refreshing);
+
+		inner.attempt = 1;
+		inner.stale = false;
+
+		self.run(inner, start);
+		changed
+	}
+
+	/// Spawns the future for the current key, delayed until `start` if given.
+	fn run(&self, inner: &mut AsyncInner<T, E>, start: Option<Instant>) {
+		let this = inner.this.clone();
+		let cancel = inner.job.token();
+
+		// delayed runs read the key only once they start,
+		// so keys skipped by debouncing never reach `func`
+		let future = match start {
+			Some(_) => None,
+			None => Some(inner.effect.invoke(cancel.clone())),
+		};
+
+		inner.job.spawn(async move {
+			let future = match future {
+				Some(future) => future,
+				None => {
+					if let Some(start) = start {
+						tokio::time::sleep_until(start).await;
+					}
+
+					let Some(body) = this.upgrade() else {
+						return;
+					};
+
+					let mut inner = body.inner.lock();
+					if cancel.is_cancelled() {
+						return;
+					}
+
+					let future = inner.effect.invoke(cancel.clone());
+
+					// a retry is in flight from now on, no longer scheduled
+					if body.transition(&mut inner, AsyncState::refreshing) {
+						Self::notify(inner);
+					}
+
+					future
+				}
+			};
+
+			let result = future.await;
+			let Some(this) = this.upgrade() else {
+				return;
+			};
+
+			this.resolve(&cancel, result);
+		});
 	}
 
-	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		self.inner.lock().not_used_by(derived);
+	/// Moves the stored state forward and returns whether its version changed.
+	fn transition(
+		&self,
+		inner: &mut AsyncInner<T, E>,
+		func: impl FnOnce(AsyncState<T, E>) -> AsyncState<T, E>,
+	) -> bool {
+		inner.fingerprint.transition(&self.value, func)
 	}
 
-	pub fn inner_update(&self, inner_mut: &mut AsyncInner<T>) {
-		if inner_mut.state == State::Valid {
+	fn resolve(&self, cancel: &CancellationToken, result: Result<T, E>) {
+		let mut inner = self.inner.lock();
+
+		// the run was superseded while it was finishing
+		if cancel.is_cancelled() {
 			return;
 		}
 
-		let is_valid = match inner_mut.state {
-			State::Valid => true,
-			State::Invalid(Invalid::Definitely) => false,
-			State::Invalid(Invalid::Maybe) => inner_mut.dependencies.are_valid(),
+		inner.job.finish();
+
+		let retry = match &result {
+			Err(error) => inner
+				.retry
+				.as_ref()
+				.and_then(|retry| retry.delay(error, inner.attempt)),
+			Ok(_) => None,
+		};
+
+		let changed = match (retry, result) {
+			(Some(delay), Err(error)) => {
+				inner.attempt += 1;
+				let attempt = Attempt {
+					number: inner.attempt,
+					at: Instant::now() + delay,
+				};
+
+				let changed = self.transition(&mut inner, |state| state.retrying(error, attempt));
+				self.run(&mut inner, Some(attempt.at));
+				changed
+			}
+			(_, result) => {
+				let changed = self.transition(&mut inner, |state| state.resolve(result));
+				self.schedule_refetch(&mut inner);
+				changed
+			}
 		};
 
-		if is_valid {
-			inner_mut.state = State::Valid;
+		if changed {
+			Self::notify(inner);
+		}
+	}
+
+	/// Plans the next periodic run, if the value is configured for it.
+	fn schedule_refetch(&self, inner: &mut AsyncInner<T, E>) {
+		let Some(interval) = inner.refetch_interval else {
+			return;
+		};
+
+		let this = inner.this.clone();
+		let cancel = inner.job.token();
+		inner.job.spawn(async move {
+			tokio::time::sleep(interval).await;
+			if let Some(this) = this.upgrade() {
+				this.refetch(&cancel);
+			}
+		});
+	}
+
+	fn refetch(&self, cancel: &CancellationToken) {
+		let mut inner = self.inner.lock();
+
+		// new inputs or a refresh started another run meanwhile
+		if cancel.is_cancelled() {
 			return;
 		}
 
-		let this = inner_mut.this.clone();
-		let evaluation = Evaluation::new(this.clone() as Weak<dyn Derived>);
-		let revision = inner_mut.effect.compute(&evaluation);
+		inner.job.finish();
 
-		if revision != inner_mut.revision {
-			inner_mut.revision = revision;
+		if inner.node.observers().is_empty() {
+			// nobody is looking, the next tracked read refetches
+			inner.stale = true;
+			return;
+		}
 
-			let future = inner_mut.effect.invoke(inner_mut.cancel.clone());
+		if self.restart(&mut inner, None) {
+			Self::notify(inner);
+		}
+	}
 
-			// respawn future
-			inner_mut.handle = Some(
-				tokio::spawn(async move {
-					let value = future.await;
-					let Some(this) = this.upgrade() else {
-						return;
-					};
+	/// Invalidates the observers after the state changed outside of an update.
+	fn notify(mut inner: MutexGuard<'_, AsyncInner<T, E>>) {
+		let notification = inner.node.notification();
+		std::mem::drop(inner);
+		notification.send();
+	}
+}
 
-					let value = Some(Hashed::new(value));
-					if **this.value.load() != value {
-						this.value.swap(Arc::new(value));
-
-						let mut inner = this.inner.lock();
-
-						// only invalidating deps, not the value itself
-						inner.used_by.retain(|item| {
-							if let Some(item) = item.upgrade() {
-								item.invalidate(Invalid::Maybe);
-								true
-							} else {
-								false
-							}
-						});
-					}
-				})
-				.abort_handle(),
-			);
+impl<T, E> AsyncNode for AsyncBody<T, E>
+where
+	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
+{
+	type Inner = AsyncInner<T, E>;
+
+	fn lock(&self) -> MutexGuard<'_, AsyncInner<T, E>> {
+		self.inner.lock()
+	}
+
+	fn node(inner: &mut AsyncInner<T, E>) -> &mut Node {
+		&mut inner.node
+	}
+
+	fn version(inner: &AsyncInner<T, E>) -> u64 {
+		inner.fingerprint.version()
+	}
+
+	fn inner_update(&self, inner_mut: &mut AsyncInner<T, E>) {
+		if inner_mut.node.is_valid(true) {
+			return;
 		}
 
-		// inner_mut.future = Some(future);
-		inner_mut.state = State::Valid;
+		let evaluation = Evaluation::new(inner_mut.node.this());
+		let addr = derivation::addr(inner_mut.this.as_ptr());
+		let revision = derivation::run(Kind::Computed, inner_mut.node.name(), addr, || {
+			inner_mut.effect.compute(&evaluation)
+		});
+
+		if Some(revision) != inner_mut.revision {
+			inner_mut.revision = Some(revision);
 
-		let parent = inner_mut.this.clone() as Weak<dyn Derived>;
-		inner_mut.dependencies.swap(evaluation.take(), &parent);
+			// observers were already invalidated together with this node,
+			// they will pick up the new version when they re-read it
+			let start = inner_mut.pacer.schedule();
+			self.restart(inner_mut, start);
+		}
+
+		inner_mut.node.set_valid();
+		inner_mut.node.swap(evaluation.take());
 	}
 }
 
-impl<T> AsyncInner<T>
+impl<T, E> Access<AsyncState<T, E>> for AsyncBody<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	pub(crate) fn used_by(&mut self, observable: Weak<dyn Derived>) {
-		self.used_by.insert(WeakAddr::new(observable));
+	fn get(&self, tracker: &Evaluation) -> crate::arc::value::Ref<'_, AsyncState<T, E>> {
+		crate::arc::value::Ref::Guard(self.state(tracker))
 	}
 
-	fn not_used_by(&mut self, derived: &Weak<dyn Derived>) {
-		self.used_by.remove(&WeakAddr::new(derived.clone()));
+	fn get_once(&self) -> crate::arc::value::Ref<'_, AsyncState<T, E>> {
+		crate::arc::value::Ref::Guard(self.state_once())
 	}
 }
 
-impl<T> Observable for AsyncBody<T>
+impl<T, E> From<Async<T, E>> for Value<AsyncState<T, E>>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	fn update(&self) -> Version {
-		self.inner_update(&mut self.inner.lock());
-		self.version()
+	fn from(value: Async<T, E>) -> Self {
+		Value::new(value.body)
 	}
+}
 
-	fn version(&self) -> Version {
-		// FIXME
-		Version::Hash(0)
+#[cfg(test)]
+mod tests {
+	use futures::executor::LocalPool;
+
+	use super::*;
+	use crate::arc::{batch, Computed, Reaction, Var};
+	use crate::spawner::LocalPoolSpawner;
+
+	#[tokio::test]
+	async fn cancels_previous_revision() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			{
+				let runs = runs.clone();
+				move |&value, cancel: CancellationToken| {
+					runs.lock().push(cancel.clone());
+					Box::pin(async move {
+						cancel.cancelled().await;
+						value
+					})
+				}
+			},
+		);
+
+		let c = Computed::new(Box::new({
+			let b = b.clone();
+			move |cx| b.get(cx)
+		}));
+
+		let _ = c.get_once();
+		assert_eq!(runs.lock().len(), 1);
+
+		a.set(2);
+		let _ = c.get_once();
+		assert_eq!(runs.lock().len(), 2);
+		assert!(runs.lock()[0].is_cancelled());
+		assert!(!runs.lock()[1].is_cancelled());
+
+		drop(c);
+		drop(b);
+		assert!(runs.lock()[1].is_cancelled());
 	}
 
-	fn used_by(&self, derived: Weak<dyn Derived>) {
-		AsyncBody::used_by(self, derived)
+	#[test]
+	fn runs_on_local_pool() {
+		let mut pool = LocalPool::new();
+		let a = Var::new(1);
+
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			|&value, _| async move { value * 2 }.boxed(),
+		)
+		.with_spawner(LocalPoolSpawner::new(&pool));
+
+		assert_eq!(*b.state_once(), AsyncState::Pending);
+		pool.run_until_stalled();
+		assert_eq!(*b.state_once(), AsyncState::Ready(2));
+
+		a.set(2);
+		assert_eq!(*b.state_once(), AsyncState::Refreshing(2));
+		pool.run_until_stalled();
+		assert_eq!(*b.state_once(), AsyncState::Ready(4));
 	}
 
-	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		AsyncBody::not_used_by(self, derived)
+	#[test]
+	fn composes_with_value() {
+		let mut pool = LocalPool::new();
+		let a = Var::new(1);
+
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			|&value, _| async move { value * 2 }.boxed(),
+		)
+		.with_spawner(LocalPoolSpawner::new(&pool));
+
+		let value: Value<AsyncState<i32>> = b.clone().into();
+		let c = Computed::new(Box::new(move |cx| value.get(cx).value().copied()));
+
+		assert_eq!(*c.get_once(), None);
+		pool.run_until_stalled();
+		assert_eq!(*c.get_once(), Some(2));
+		assert_eq!(b.get_once(), Some(2));
 	}
-}
 
-// impl<T> Access<T> for AsyncBody<T>
-// where
-// 	T: Send + Sync + Hash + 'static,
-// {
-// 	fn get(&self, tracker: &Evaluation) -> crate::value::Ref<'_, T> {
-// 		crate::value::Ref::Guard(self.get(tracker))
-// 	}
+	#[test]
+	fn orders_reactions_by_depth() {
+		let mut pool = LocalPool::new();
+		let a = Var::new(1);
+
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			|&value, _| async move { value * 2 }.boxed(),
+		)
+		.with_spawner(LocalPoolSpawner::new(&pool));
+
+		let runs = Arc::new(Mutex::new(Vec::new()));
+		let after = Reaction::new(Box::new({
+			let (b, runs) = (b.clone(), runs.clone());
+			move |cx| {
+				let _ = b.state(cx);
+				runs.lock().push("after");
+			}
+		}));
+		let before = Reaction::new(Box::new({
+			let (a, runs) = (a.clone(), runs.clone());
+			move |cx| {
+				let _ = a.get(cx);
+				runs.lock().push("before");
+			}
+		}));
+		after.update();
+		before.update();
+		pool.run_until_stalled();
+
+		runs.lock().clear();
+		batch(|| a.set(2));
+		assert_eq!(*runs.lock(), vec!["before", "after"]);
+	}
 
-// 	fn get_once(&self) -> crate::value::Ref<'_, T> {
-// 		crate::value::Ref::Guard(self.get_once())
-// 	}
-// }
+	#[tokio::test(start_paused = true)]
+	async fn debounces_input_changes() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			{
+				let runs = runs.clone();
+				move |&value, _| {
+					let runs = runs.clone();
+					async move {
+						runs.lock().push(value);
+						value * 2
+					}
+					.boxed()
+				}
+			},
+		)
+		.with_debounce(Duration::from_millis(100));
+
+		for value in 2..=4 {
+			assert!(b.state_once().is_loading());
+			tokio::time::sleep(Duration::from_millis(50)).await;
+			a.set(value);
+		}
 
-impl<T: 'static> Derived for AsyncBody<T>
-where
-	T: Send + Sync + Hash + 'static,
-{
-	fn invalidate(self: Arc<Self>, invalid: crate::arc::Invalid) {
-		let mut self_mut = self.inner.lock();
-		if matches!(self_mut.state, State::Valid) {
-			self_mut.state = State::Invalid(invalid);
-			self_mut.used_by.retain(|item| {
-				if let Some(item) = item.upgrade() {
-					item.invalidate(Invalid::Maybe);
-					true
-				} else {
-					false
+		assert_eq!(*b.state_once(), AsyncState::Pending);
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert_eq!(*runs.lock(), vec![4]);
+		assert_eq!(*b.state_once(), AsyncState::Ready(8));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn throttles_input_changes() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			{
+				let runs = runs.clone();
+				move |&value, _| {
+					let runs = runs.clone();
+					async move {
+						runs.lock().push(value);
+						value
+					}
+					.boxed()
 				}
-			});
+			},
+		)
+		.with_throttle(Duration::from_millis(100));
+
+		let _ = b.state_once();
+		tokio::task::yield_now().await;
+		assert_eq!(*runs.lock(), vec![1]);
+
+		for value in 2..=4 {
+			a.set(value);
+			let _ = b.state_once();
+			tokio::time::sleep(Duration::from_millis(20)).await;
 		}
+
+		assert_eq!(*runs.lock(), vec![1]);
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(*runs.lock(), vec![1, 4]);
+		assert_eq!(*b.state_once(), AsyncState::Ready(4));
 	}
-}
 
-// impl<T> From<Async<T>> for Value<T>
-// where
-// 	T: Send + Sync + Hash + 'static,
-// {
-// 	fn from(computed: Async<T>) -> Self {
-// 		Value::new(computed.body)
-// 	}
-// }
+	#[tokio::test(start_paused = true)]
+	async fn retries_with_backoff() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(0));
+
+		let b = Async::new_fallible(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			{
+				let runs = runs.clone();
+				move |&value: &i32, _| {
+					*runs.lock() += 1;
+					async move { Err::<i32, _>(format!("{value} failed")) }.boxed()
+				}
+			},
+		)
+		.with_retry(
+			Retry::new(3)
+				.backoff(Duration::from_millis(100))
+				.jitter(false)
+				.when(|error: &String| error.ends_with("failed")),
+		);
+
+		let start = Instant::now();
+		let _ = b.state_once();
+		tokio::task::yield_now().await;
+		assert_eq!(
+			*b.state_once(),
+			AsyncState::Retrying(
+				"1 failed".to_string(),
+				None,
+				Attempt {
+					number: 2,
+					at: start + Duration::from_millis(100),
+				}
+			)
+		);
+
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert_eq!(*runs.lock(), 2);
+		assert_eq!(
+			b.state_once().attempt().map(|attempt| attempt.at),
+			Some(start + Duration::from_millis(300))
+		);
+
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		assert_eq!(*runs.lock(), 3);
+		assert_eq!(
+			*b.state_once(),
+			AsyncState::Failed("1 failed".to_string(), None)
+		);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn leaves_retrying_once_the_retry_runs() {
+		let runs = Arc::new(Mutex::new(0));
+
+		let b = Async::new_fallible(|_| (), {
+			let runs = runs.clone();
+			move |_, _| {
+				let run = {
+					let mut runs = runs.lock();
+					*runs += 1;
+					*runs
+				};
+
+				async move {
+					if run == 1 {
+						return Err("failed".to_string());
+					}
+
+					tokio::time::sleep(Duration::from_millis(100)).await;
+					Ok(run)
+				}
+				.boxed()
+			}
+		})
+		.with_retry(
+			Retry::new(2)
+				.backoff(Duration::from_millis(100))
+				.jitter(false),
+		);
+
+		let c = Computed::new(Box::new({
+			let b = b.clone();
+			move |cx| b.state(cx).attempt().is_some()
+		}));
+
+		assert!(!*c.get_once());
+		tokio::task::yield_now().await;
+		assert!(*c.get_once());
+
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert!(!*c.get_once());
+		assert_eq!(*b.state_once(), AsyncState::Pending);
+
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(*b.state_once(), AsyncState::Ready(2));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn refetches_while_observed() {
+		let runs = Arc::new(Mutex::new(0));
+
+		let b = Async::new(|_| (), {
+			let runs = runs.clone();
+			move |_, _| {
+				let run = {
+					let mut runs = runs.lock();
+					*runs += 1;
+					*runs
+				};
+
+				async move { run }.boxed()
+			}
+		})
+		.with_refetch_interval(Duration::from_millis(100));
+
+		let c = Computed::new(Box::new({
+			let b = b.clone();
+			move |cx| b.get(cx)
+		}));
+
+		assert_eq!(*c.get_once(), None);
+		tokio::task::yield_now().await;
+		assert_eq!(*c.get_once(), Some(1));
+
+		b.refresh();
+		assert_eq!(*b.state_once(), AsyncState::Refreshing(1));
+		tokio::task::yield_now().await;
+		assert_eq!(*c.get_once(), Some(2));
+
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert_eq!(*c.get_once(), Some(3));
+
+		drop(c);
+		tokio::time::sleep(Duration::from_millis(300)).await;
+		assert_eq!(*runs.lock(), 3);
+
+		let c = Computed::new(Box::new({
+			let b = b.clone();
+			move |cx| b.get(cx)
+		}));
+
+		assert_eq!(*c.get_once(), Some(3));
+		tokio::task::yield_now().await;
+		assert_eq!(*c.get_once(), Some(4));
+	}
+}
@@ -1,38 +1,45 @@
 use std::any::Any;
-use std::collections::BTreeSet;
+use std::convert::Infallible;
 use std::hash::Hash;
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
-use futures::Future;
-use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use parking_lot::{MappedRwLockReadGuard, Mutex, MutexGuard, RwLock, RwLockReadGuard};
 use tokio::sync::Notify;
-use tokio::task::AbortHandle;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 
-use crate::arc::addr::WeakAddr;
-use crate::arc::dependencies::Dependencies;
-use crate::arc::{Derived, Evaluation, Invalid, Observable, State, Version};
+use crate::arc::node::{AsyncNode, Fingerprint, Job, Node};
+use crate::arc::value::{Access, Value};
+use crate::arc::{Derived, Evaluation};
+use crate::async_state::{AsyncState, Attempt, NotReady};
 use crate::capture::Capture;
-use crate::hashed::Hashed;
+use crate::pace::Pacer;
+use crate::retry::Retry;
+use crate::spawner::Spawner;
 
 #[doc(hidden)]
-pub struct Async<T>
+pub struct Async<T, E = Infallible>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	body: Arc<AsyncBody<T>>,
+	body: Arc<AsyncBody<T, E>>,
 }
 
-pub struct AsyncBody<T>
+pub struct AsyncBody<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	value: RwLock<(Option<T>, u64)>,
+	value: RwLock<AsyncState<T, E>>,
 	notify: Notify,
-	inner: Mutex<AsyncInner<T>>,
+	inner: Mutex<AsyncInner<T, E>>,
 }
 
-struct AsyncEffect<T, H: Fn(AsyncContext, C) -> F, F: Future<Output = T>, C>
+struct AsyncEffect<T, E, H: Fn(AsyncContext, C) -> F, F: Future<Output = Result<T, E>>, C>
 where
 	H: Send + 'static,
 	F: Send + 'static,
@@ -41,49 +48,38 @@ where
 	func: H,
 }
 
-impl<T, H: Fn(AsyncContext, C) -> F, F: Future<Output = T>, C> AsyncEffecty<T>
-	for AsyncEffect<T, H, F, C>
+impl<T, E, H: Fn(AsyncContext, C) -> F, F: Future<Output = Result<T, E>>, C> AsyncEffecty<T, E>
+	for AsyncEffect<T, E, H, F, C>
 where
 	T: Hash + Send + Sync + 'static,
+	E: Send + Sync + 'static,
 	H: Send + 'static,
 	F: Send + 'static,
 	C: Clone + Send + 'static,
 {
-	fn invoke(&mut self, ctx: Weak<AsyncBody<T>>) -> tokio::task::AbortHandle {
-		let this = ctx.upgrade().unwrap();
-		let future = (self.func)(
-			AsyncContext {
-				evaluation: this.inner.lock().eval.clone(),
-			},
-			self.capture.clone(),
-		);
-
-		tokio::spawn(async move {
-			let result = future.await;
-			let this = ctx.upgrade().unwrap();
-			this.set(result);
-		})
-		.abort_handle()
+	fn invoke(&mut self, cx: AsyncContext) -> BoxFuture<'static, Result<T, E>> {
+		Box::pin((self.func)(cx, self.capture.clone()))
 	}
 }
 
-pub trait AsyncEffecty<T: Send + Sync + Hash>: Send {
-	fn invoke(&mut self, ctx: Weak<AsyncBody<T>>) -> AbortHandle;
+pub trait AsyncEffecty<T: Send + Sync + Hash, E: Send + Sync>: Send {
+	fn invoke(&mut self, cx: AsyncContext) -> BoxFuture<'static, Result<T, E>>;
 }
 
-pub struct AsyncInner<T>
+pub struct AsyncInner<T, E>
 where
 	T: Send + Hash + Sync + 'static,
+	E: Send + Sync + 'static,
 {
-	effect: Box<dyn AsyncEffecty<T>>,
-	cancel: CancellationToken,
+	effect: Box<dyn AsyncEffecty<T, E>>,
 	eval: Arc<Evaluation>,
-	revision: u64,
-	handle: Option<AbortHandle>,
-	state: State,
-	used_by: BTreeSet<WeakAddr<dyn Derived>>,
-	dependencies: Dependencies,
-	this: Weak<AsyncBody<T>>,
+	fingerprint: Fingerprint,
+	job: Job,
+	pacer: Pacer,
+	retry: Option<Retry<E>>,
+	attempt: u32,
+	node: Node,
+	this: Weak<AsyncBody<T, E>>,
 }
 
 impl<T> Async<T>
@@ -94,249 +90,372 @@ where
 		capture: C,
 		func: impl Fn(AsyncContext, C::Output) -> F + Send + 'static,
 	) -> Self
+	where
+		C::Output: Clone + Send + 'static,
+	{
+		Async::new_fallible(capture, move |cx, capture| func(cx, capture).map(Ok))
+	}
+}
+
+impl<T, E> Async<T, E>
+where
+	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
+{
+	/// Same as [`Async::new`], but the future can fail. A failure is
+	/// reported as [`AsyncState::Failed`] and keeps the last good value.
+	pub fn new_fallible<C: Capture, F: Future<Output = Result<T, E>> + Send + 'static>(
+		capture: C,
+		func: impl Fn(AsyncContext, C::Output) -> F + Send + 'static,
+	) -> Self
 	where
 		C::Output: Clone + Send + 'static,
 	{
 		Async {
 			body: Arc::new_cyclic(|this| AsyncBody {
-				value: RwLock::new((None, 0)),
+				value: RwLock::new(AsyncState::Pending),
 				notify: Notify::new(),
 				inner: Mutex::new(AsyncInner {
 					effect: Box::new(AsyncEffect {
 						func,
 						capture: capture.capture(),
-					}) as Box<dyn AsyncEffecty<T>>,
-					revision: 0,
+					}) as Box<dyn AsyncEffecty<T, E>>,
 					eval: Arc::new(Evaluation::new(this.clone() as Weak<dyn Derived>)),
-					handle: None,
-					cancel: CancellationToken::new(),
-					state: State::Invalid(Invalid::Definitely),
-					used_by: BTreeSet::new(),
-					dependencies: Dependencies::new(),
+					fingerprint: Fingerprint::new::<T, E>(),
+					job: Job::default(),
+					pacer: Pacer::default(),
+					retry: None,
+					attempt: 0,
+					node: Node::new("<unnamed>", this.clone() as Weak<dyn Derived>),
 					this: this.clone(),
 				}),
 			}),
 		}
 	}
 
-	pub async fn ready_once(&self) -> MappedRwLockReadGuard<T>
+	pub async fn ready_once(&self) -> MappedRwLockReadGuard<'_, T>
 	where
 		T: std::fmt::Debug,
 	{
 		loop {
+			let notified = self.body.notify.notified();
+			if let Ok(value) = MappedRwLockReadGuard::try_map(self.body.state_once(), |s| s.value())
 			{
-				let value = self.body.get_once();
-				if value.is_some() {
-					return MappedRwLockReadGuard::try_map(value, |t| Some(t.as_ref().unwrap()))
-						.expect("Unreachable");
-				}
+				return value;
 			}
-			self.body.notify.notified().await;
+			notified.await;
 		}
 	}
 
-	pub async fn ready<'a>(&self, cx: &'a impl AsRef<Evaluation>) -> MappedRwLockReadGuard<T>
+	pub async fn ready(&self, cx: &impl AsRef<Evaluation>) -> MappedRwLockReadGuard<'_, T>
 	where
 		T: std::fmt::Debug,
 	{
 		loop {
+			let notified = self.body.notify.notified();
+			if let Ok(value) =
+				MappedRwLockReadGuard::try_map(self.body.state(cx.as_ref()), |s| s.value())
 			{
-				let value = self.body.get(cx.as_ref());
-				if value.is_some() {
-					return MappedRwLockReadGuard::try_map(value, |t| Some(t.as_ref().unwrap()))
-						.expect("Unreachable");
-				}
+				return value;
 			}
-			self.body.notify.notified().await;
+			notified.await;
 		}
 	}
 
-	// #[inline]
-	// pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
-	// 	self.body.get_once()
-	// }
+	/// The latest known value, fresh or stale, without tracking.
+	#[inline]
+	pub fn get_once(&self) -> Option<MappedRwLockReadGuard<'_, T>> {
+		MappedRwLockReadGuard::try_map(self.body.state_once(), |s| s.value()).ok()
+	}
 
+	/// The latest known value, fresh or stale.
 	#[inline]
-	pub fn get<'a>(&'a self, cx: &'a impl AsRef<Evaluation>) -> MappedRwLockReadGuard<Option<T>> {
-		self.body.get(cx.as_ref())
+	pub fn get<'a>(
+		&'a self,
+		cx: &'a impl AsRef<Evaluation>,
+	) -> Option<MappedRwLockReadGuard<'a, T>> {
+		MappedRwLockReadGuard::try_map(self.body.state(cx.as_ref()), |s| s.value()).ok()
+	}
+
+	/// Same as [`Async::get`], but fails with [`NotReady`] while there is
+	/// no value, so derivations can short-circuit with `?`. A first run
+	/// that failed is reported with its error instead of as pending.
+	#[inline]
+	pub fn read<'a>(
+		&'a self,
+		cx: &'a impl AsRef<Evaluation>,
+	) -> Result<MappedRwLockReadGuard<'a, T>, NotReady<E>>
+	where
+		E: Clone,
+	{
+		let state = self.body.state(cx.as_ref());
+		state.read()?;
+
+		Ok(MappedRwLockReadGuard::map(state, |s| s.value().unwrap()))
+	}
+
+	/// Runs futures of this value with `spawner` instead of
+	/// the [default](crate::spawner::default) one.
+	pub fn with_spawner(self, spawner: impl Spawner + 'static) -> Self {
+		self.body.inner.lock().job.set_spawner(Arc::new(spawner));
+		self
+	}
+
+	/// Starts a run only once the dependencies of the previous run stayed
+	/// the same for `duration`, the state shows loading meanwhile.
+	/// Waiting uses tokio time, so runs need the tokio timer.
+	pub fn with_debounce(self, duration: Duration) -> Self {
+		self.body.inner.lock().pacer.debounce(duration);
+		self
+	}
+
+	/// Starts at most one run per `duration`, with the latest inputs.
+	/// Waiting uses tokio time, so runs need the tokio timer.
+	pub fn with_throttle(self, duration: Duration) -> Self {
+		self.body.inner.lock().pacer.throttle(duration);
+		self
+	}
+
+	/// Runs the future again when it fails, as long as `retry` allows.
+	/// Scheduled retries are reported as [`AsyncState::Retrying`].
+	pub fn with_retry(self, retry: Retry<E>) -> Self {
+		self.body.inner.lock().retry = Some(retry);
+		self
+	}
+
+	#[inline]
+	pub fn state_once(&self) -> MappedRwLockReadGuard<'_, AsyncState<T, E>> {
+		self.body.state_once()
+	}
+
+	#[inline]
+	pub fn state<'a>(
+		&'a self,
+		cx: &'a impl AsRef<Evaluation>,
+	) -> MappedRwLockReadGuard<'a, AsyncState<T, E>> {
+		self.body.state(cx.as_ref())
 	}
 }
 
-impl<T> AsyncBody<T>
+impl<T, E> AsyncBody<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	// pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
-	// 	self.update();
-	// 	MappedRwLockReadGuard::map(
-	// 		RwLockReadGuard::map(self.value.read(), |s| s.as_ref().unwrap()),
-	// 		|s| &s.value,
-	// 	)
-	// }
+	/// Moves the stored state forward and returns whether its version changed.
+	fn transition(
+		&self,
+		inner: &mut AsyncInner<T, E>,
+		func: impl FnOnce(AsyncState<T, E>) -> AsyncState<T, E>,
+	) -> bool {
+		inner.fingerprint.transition(&self.value, func)
+	}
+
+	fn resolve(&self, cancel: &CancellationToken, result: Result<T, E>) {
+		let mut inner = self.inner.lock();
+
+		// the run was superseded while it was finishing
+		if cancel.is_cancelled() {
+			return;
+		}
 
-	pub fn set(&self, value: T) {
-		let Hashed { value, hash } = Hashed::new(value);
-		let value = (Some(value), hash);
+		inner.job.finish();
 
-		let changed = { self.value.read().1 != value.1 };
+		let dependencies = inner.eval.take_dependencies();
+		inner.node.swap(dependencies);
+
+		let retry = match &result {
+			Err(error) => inner
+				.retry
+				.as_ref()
+				.and_then(|retry| retry.delay(error, inner.attempt)),
+			Ok(_) => None,
+		};
+
+		let changed = match (retry, result) {
+			(Some(delay), Err(error)) => {
+				inner.attempt += 1;
+				let attempt = Attempt {
+					number: inner.attempt,
+					at: Instant::now() + delay,
+				};
+
+				let changed = self.transition(&mut inner, |state| state.retrying(error, attempt));
+				self.run(&mut inner, Some(attempt.at));
+				changed
+			}
+			(_, result) => self.transition(&mut inner, |state| state.resolve(result)),
+		};
 
 		if changed {
-			let mut inner = self.inner.lock();
-			*self.value.write() = value;
-
-			// only invalidating deps, not the value itself
-			inner.used_by.retain(|item| {
-				if let Some(item) = item.upgrade() {
-					item.invalidate(Invalid::Maybe);
-					true
-				} else {
-					false
-				}
-			});
+			self.notify(inner);
 		}
 	}
 
-	pub fn get_once(&self) -> MappedRwLockReadGuard<Option<T>> {
-		{
-			let mut self_mut = self.inner.lock();
-			self.inner_update(&mut self_mut);
-			RwLockReadGuard::map(self.value.read(), |v| &v.0)
-		}
+	/// Wakes the waiters and invalidates the observers
+	/// after the state changed outside of an update.
+	fn notify(&self, mut inner: MutexGuard<'_, AsyncInner<T, E>>) {
+		let notification = inner.node.notification();
+		std::mem::drop(inner);
+
+		self.notify.notify_waiters();
+		notification.send();
+	}
+
+	pub fn state_once(&self) -> MappedRwLockReadGuard<'_, AsyncState<T, E>> {
+		self.inner_update(&mut self.inner.lock());
+		RwLockReadGuard::map(self.value.read(), |v| v)
 	}
 
-	pub fn get<'a>(&'a self, eval: &'_ Evaluation) -> MappedRwLockReadGuard<Option<T>> {
+	pub fn state(&self, eval: &'_ Evaluation) -> MappedRwLockReadGuard<'_, AsyncState<T, E>> {
 		{
 			let mut self_mut = self.inner.lock();
 			self.inner_update(&mut self_mut);
-			eval.based_on(
-				self_mut.this.upgrade().unwrap(),
-				Version::Hash(self_mut.revision),
-			);
-			self_mut.used_by(eval.parent());
 
-			RwLockReadGuard::map(self.value.read(), |v| &v.0)
+			let this = self_mut.this.upgrade().unwrap();
+			let version = self_mut.fingerprint.version();
+			self_mut.node.track(this, version, eval);
 		}
-	}
-
-	pub(crate) fn used_by(&self, observable: Weak<dyn Derived>) {
-		self.inner.lock().used_by(observable);
-	}
 
-	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		self.inner.lock().not_used_by(derived);
+		RwLockReadGuard::map(self.value.read(), |v| v)
 	}
 
-	pub fn inner_update(&self, inner_mut: &mut AsyncInner<T>) {
-		if inner_mut.state == State::Valid {
-			return;
-		}
+	/// Spawns a run with a fresh evaluation, delayed until `start` if given.
+	fn run(&self, inner: &mut AsyncInner<T, E>, start: Option<Instant>) {
+		let this = inner.this.clone();
+		let cancel = inner.job.token();
 
-		let is_valid = match inner_mut.state {
-			State::Valid => true,
-			State::Invalid(Invalid::Definitely) => false,
-			State::Invalid(Invalid::Maybe) => inner_mut.dependencies.are_valid(),
+		// delayed runs build their future only once they start,
+		// so runs skipped by debouncing never reach `func`
+		let future = match start {
+			Some(_) => None,
+			None => Some(inner.invoke()),
 		};
 
-		// if is_valid {
-		// 	inner_mut.state = State::Valid;
-		// 	return;
-		// }
+		inner.job.spawn(async move {
+			let future = match future {
+				Some(future) => future,
+				None => {
+					if let Some(start) = start {
+						tokio::time::sleep_until(start).await;
+					}
+
+					let Some(body) = this.upgrade() else {
+						return;
+					};
 
-		let this = inner_mut.this.clone();
+					let mut inner = body.inner.lock();
+					if cancel.is_cancelled() {
+						return;
+					}
 
-		// clear dependencies list before evaluating future again
-		// inner_mut.eval.take();
-		// inner_mut.future = Some(future);
-		// inner_mut.state = State::Valid;
+					let future = inner.invoke();
 
-		inner_mut.effect.invoke(this);
+					// a retry is in flight from now on, no longer scheduled
+					if body.transition(&mut inner, AsyncState::refreshing) {
+						body.notify(inner);
+					}
+
+					future
+				}
+			};
 
-		let parent = inner_mut.this.clone() as Weak<dyn Derived>;
-		// inner_mut.dependencies.swap(evaluation.take(), &parent);
+			let result = future.await;
+			let Some(this) = this.upgrade() else {
+				return;
+			};
+
+			this.resolve(&cancel, result);
+		});
 	}
 }
 
-impl<T> AsyncInner<T>
+impl<T, E> AsyncInner<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	pub(crate) fn used_by(&mut self, observable: Weak<dyn Derived>) {
-		self.used_by.insert(WeakAddr::new(observable));
-	}
-
-	fn not_used_by(&mut self, derived: &Weak<dyn Derived>) {
-		self.used_by.remove(&WeakAddr::new(derived.clone()));
+	/// Builds the future of a run, tracking into a fresh evaluation.
+	fn invoke(&mut self) -> BoxFuture<'static, Result<T, E>> {
+		let evaluation = Arc::new(Evaluation::new(self.node.this()));
+		self.eval = evaluation.clone();
+
+		self.effect.invoke(AsyncContext {
+			evaluation,
+			cancel: self.job.token(),
+		})
 	}
 }
 
-impl<T> Observable for AsyncBody<T>
+impl<T, E> AsyncNode for AsyncBody<T, E>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	fn update(&self) -> Version {
-		self.inner_update(&mut self.inner.lock());
-		self.version()
+	type Inner = AsyncInner<T, E>;
+
+	fn lock(&self) -> MutexGuard<'_, AsyncInner<T, E>> {
+		self.inner.lock()
 	}
 
-	fn version(&self) -> Version {
-		// FIXME
-		Version::Hash(0)
+	fn node(inner: &mut AsyncInner<T, E>) -> &mut Node {
+		&mut inner.node
 	}
 
-	fn used_by(&self, derived: Weak<dyn Derived>) {
-		AsyncBody::used_by(self, derived)
+	fn version(inner: &AsyncInner<T, E>) -> u64 {
+		inner.fingerprint.version()
 	}
 
-	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		AsyncBody::not_used_by(self, derived)
+	fn inner_update(&self, inner_mut: &mut AsyncInner<T, E>) {
+		// dependencies of a run that is still in flight are not known yet
+		let is_running = inner_mut.job.is_running();
+		if inner_mut.node.is_valid(!is_running) {
+			return;
+		}
+
+		// a new run makes the previous one stale
+		inner_mut.job.restart();
+
+		// observers were already invalidated together with this node,
+		// they will pick up the new version when they re-read it
+		self.transition(inner_mut, AsyncState::refreshing);
+		inner_mut.attempt = 1;
+
+		let start = inner_mut.pacer.schedule();
+		self.run(inner_mut, start);
+
+		inner_mut.node.set_valid();
 	}
 }
 
-// impl<T> Access<T> for AsyncBody<T>
-// where
-// 	T: Send + Sync + Hash + 'static,
-// {
-// 	fn get(&self, tracker: &Evaluation) -> crate::value::Ref<'_, T> {
-// 		crate::value::Ref::Guard(self.get(tracker))
-// 	}
+impl<T, E> Access<AsyncState<T, E>> for AsyncBody<T, E>
+where
+	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
+{
+	fn get(&self, tracker: &Evaluation) -> crate::arc::value::Ref<'_, AsyncState<T, E>> {
+		crate::arc::value::Ref::Guard(self.state(tracker))
+	}
 
-// 	fn get_once(&self) -> crate::value::Ref<'_, T> {
-// 		crate::value::Ref::Guard(self.get_once())
-// 	}
-// }
+	fn get_once(&self) -> crate::arc::value::Ref<'_, AsyncState<T, E>> {
+		crate::arc::value::Ref::Guard(self.state_once())
+	}
+}
 
-impl<T: 'static> Derived for AsyncBody<T>
+impl<T, E> From<Async<T, E>> for Value<AsyncState<T, E>>
 where
 	T: Send + Sync + Hash + 'static,
+	E: Send + Sync + 'static,
 {
-	fn invalidate(self: Arc<Self>, invalid: crate::arc::Invalid) {
-		let mut self_mut = self.inner.lock();
-		if matches!(self_mut.state, State::Valid) {
-			self_mut.state = State::Invalid(invalid);
-			self_mut.used_by.retain(|item| {
-				if let Some(item) = item.upgrade() {
-					item.invalidate(Invalid::Maybe);
-					true
-				} else {
-					false
-				}
-			});
-		}
+	fn from(value: Async<T, E>) -> Self {
+		Value::new(value.body)
 	}
 }
 
-// impl<T> From<Async<T>> for Value<T>
-// where
-// 	T: Send + Sync + Hash + 'static,
-// {
-// 	fn from(computed: Async<T>) -> Self {
-// 		Value::new(computed.body)
-// 	}
-// }
-
-impl<T> Clone for Async<T>
+impl<T, E> Clone for Async<T, E>
 where
 	T: Send + Sync + Hash,
+	E: Send + Sync,
 {
 	fn clone(&self) -> Self {
 		Self {
@@ -345,31 +464,31 @@ where
 	}
 }
 
-impl<T: Send + Sync + Hash + 'static> From<Async<T>> for Arc<dyn Any> {
-	fn from(var: Async<T>) -> Self {
+impl<T: Send + Sync + Hash + 'static, E: Send + Sync + 'static> From<Async<T, E>> for Arc<dyn Any> {
+	fn from(var: Async<T, E>) -> Self {
 		var.body
 	}
 }
 
-impl<T: Send + Sync + Hash + 'static> TryFrom<Arc<dyn Any + Send + Sync>> for Async<T> {
+impl<T: Send + Sync + Hash + 'static, E: Send + Sync + 'static> TryFrom<Arc<dyn Any + Send + Sync>>
+	for Async<T, E>
+{
 	type Error = Arc<dyn Any + Send + Sync>;
 	fn try_from(value: Arc<dyn Any + Send + Sync>) -> Result<Self, Self::Error> {
-		Arc::downcast::<AsyncBody<T>>(value).map(|body| Async { body })
-	}
-}
-
-impl<T> Drop for AsyncInner<T>
-where
-	T: Send + Sync + Hash + 'static,
-{
-	fn drop(&mut self) {
-		let refr = self.this.clone() as Weak<dyn Derived>;
-		self.dependencies.drop(&refr);
+		Arc::downcast::<AsyncBody<T, E>>(value).map(|body| Async { body })
 	}
 }
 
 pub struct AsyncContext {
 	evaluation: Arc<Evaluation>,
+	cancel: CancellationToken,
+}
+
+impl AsyncContext {
+	/// Token that is cancelled once this run becomes stale.
+	pub fn cancelled(&self) -> &CancellationToken {
+		&self.cancel
+	}
 }
 
 impl AsRef<Evaluation> for AsyncContext {
@@ -380,31 +499,220 @@ impl AsRef<Evaluation> for AsyncContext {
 
 #[cfg(test)]
 mod tests {
-	use tokio::sync::watch::channel;
-
 	use super::*;
 	use crate::arc::{Computed, Var};
+	use crate::async_state::Pending;
 
 	#[tokio::test]
 	async fn test() {
 		let a = Var::new(10);
 
-		let (s, r) = channel(10);
+		let b = Async::new((&a,), |cx, (a,)| async move { a.get(&cx) });
 
-		// let c = Async::new((&b,), |cx, (b,)| async move { *b.ready(&cx).await });
+		let v = b.ready_once().await;
+		assert_eq!(*v, 10);
+	}
 
-		// let d = Computed::new(Box::new(move |cx| c.get(&cx).clone()));
+	#[tokio::test]
+	async fn propagates_pending() {
+		let a = Var::new(10);
+		let b = Async::new((&a,), |cx, (a,)| async move { a.get(&cx) });
+		let c = Async::new_fallible((&b,), |cx, (b,)| async move {
+			Ok::<_, Pending>(*b.read(&cx)? + 1)
+		});
+
+		let d = Computed::new(Box::new({
+			let (b, c) = (b.clone(), c.clone());
+			move |cx| -> Result<i32, Pending> { Ok(*b.read(cx)? + *c.read(cx)?) }
+		}));
+
+		assert_eq!(*d.get_once(), Err(Pending));
+		assert_eq!(*c.ready_once().await, 11);
+		assert_eq!(*d.get_once(), Ok(21));
+	}
 
-		let b = Async::new((&a,), |cx, (a,)| async move { a.get(&cx)? });
+	#[tokio::test]
+	async fn reports_failed_first_run() {
+		let a = Var::new(-1);
+		let b = Async::new_fallible((&a,), |cx, (a,)| async move {
+			match a.get(&cx) {
+				value if value > 0 => Ok(value),
+				value => Err(format!("{} is not positive", value)),
+			}
+		});
+
+		let c = Computed::new(Box::new({
+			let b = b.clone();
+			move |cx| b.read(cx).map(|value| *value)
+		}));
+
+		assert_eq!(*c.get_once(), Err(NotReady::Pending));
+		b.body.notify.notified().await;
+		assert_eq!(
+			*c.get_once(),
+			Err(NotReady::Failed(String::from("-1 is not positive")))
+		);
+	}
 
-		// kabina.fileset()?
+	#[tokio::test]
+	async fn cancels_stale_run() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let b = Async::new((&a, &runs), |cx, (a, runs)| async move {
+			let value = a.get(&cx);
+			runs.lock().push(cx.cancelled().clone());
+			if value == 1 {
+				cx.cancelled().cancelled().await;
+			}
+			value
+		});
 
-		let v = b.ready_once().await;
-		assert_eq!(*v, 10);
+		let c = Computed::new(Box::new({
+			let b = b.clone();
+			move |cx| b.get(cx).map(|v| *v)
+		}));
+
+		assert_eq!(*c.get_once(), None);
+		tokio::task::yield_now().await;
+
+		a.set(2);
+		assert_eq!(*c.get_once(), None);
+		assert!(runs.lock()[0].is_cancelled());
+
+		assert_eq!(*b.ready_once().await, 2);
+		assert_eq!(*c.get_once(), Some(2));
+	}
+
+	#[tokio::test]
+	async fn reports_loading_states() {
+		let a = Var::new(1);
+
+		let b = Async::new_fallible((&a,), |cx, (a,)| async move {
+			match a.get(&cx) {
+				value if value > 0 => Ok(value),
+				value => Err(format!("{} is not positive", value)),
+			}
+		});
+
+		assert_eq!(*b.state_once(), AsyncState::Pending);
+		assert_eq!(*b.ready_once().await, 1);
+		assert_eq!(*b.state_once(), AsyncState::Ready(1));
+
+		a.set(-1);
+		assert_eq!(*b.state_once(), AsyncState::Refreshing(1));
+		b.body.notify.notified().await;
+		assert_eq!(
+			*b.state_once(),
+			AsyncState::Failed(String::from("-1 is not positive"), Some(1))
+		);
+
+		a.set(2);
+		assert_eq!(*b.state_once(), AsyncState::Refreshing(1));
+		b.body.notify.notified().await;
+		assert_eq!(*b.state_once(), AsyncState::Ready(2));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn debounces_input_changes() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let b = Async::new((&a, &runs), |cx, (a, runs)| async move {
+			let value = a.get(&cx);
+			runs.lock().push(value);
+			value * 2
+		})
+		.with_debounce(Duration::from_millis(100));
+
+		assert_eq!(*b.ready_once().await, 2);
+		assert_eq!(*runs.lock(), vec![1]);
+
+		for value in 2..=4 {
+			a.set(value);
+			assert!(b.state_once().is_loading());
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+
+		assert_eq!(*runs.lock(), vec![1]);
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(*runs.lock(), vec![1, 4]);
+		assert_eq!(*b.state_once(), AsyncState::Ready(8));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn throttles_input_changes() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let b = Async::new((&a, &runs), |cx, (a, runs)| async move {
+			let value = a.get(&cx);
+			runs.lock().push(value);
+			value
+		})
+		.with_throttle(Duration::from_millis(100));
+
+		assert_eq!(*b.ready_once().await, 1);
+
+		for value in 2..=4 {
+			a.set(value);
+			let _ = b.state_once();
+			tokio::time::sleep(Duration::from_millis(20)).await;
+		}
+
+		assert_eq!(*runs.lock(), vec![1]);
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(*runs.lock(), vec![1, 4]);
+		assert_eq!(*b.state_once(), AsyncState::Ready(4));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn retries_with_backoff() {
+		let a = Var::new(1);
+		let runs = Arc::new(Mutex::new(0));
 
-		// let value = d.get_once();
+		let b = Async::new_fallible((&a, &runs), |cx, (a, runs)| async move {
+			let value = a.get(&cx);
+			*runs.lock() += 1;
+			if value == 1 {
+				return Err(format!("{value} failed"));
+			}
+
+			tokio::time::sleep(Duration::from_millis(100)).await;
+			Ok(value)
+		})
+		.with_retry(
+			Retry::new(3)
+				.backoff(Duration::from_millis(100))
+				.jitter(false),
+		);
+
+		let start = Instant::now();
+		let _ = b.state_once();
+		tokio::task::yield_now().await;
+		assert_eq!(
+			*b.state_once(),
+			AsyncState::Retrying(
+				"1 failed".to_string(),
+				None,
+				Attempt {
+					number: 2,
+					at: start + Duration::from_millis(100),
+				}
+			)
+		);
+
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert_eq!(*runs.lock(), 2);
+		assert_eq!(
+			b.state_once().attempt().map(|attempt| attempt.number),
+			Some(3)
+		);
 
-		// println!("{:?}", &*value)
+		a.set(2);
+		assert_eq!(*b.state_once(), AsyncState::Pending);
+		assert_eq!(*b.ready_once().await, 2);
+		assert_eq!(*runs.lock(), 3);
 	}
 }
 
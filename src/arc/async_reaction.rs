@@ -0,0 +1,363 @@
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::arc::node::{Keyed, Node};
+use crate::arc::reaction::Order;
+use crate::arc::{Derived, Evaluation, Invalid, Reactive, Runtime};
+use crate::derivation::{self, Kind};
+use crate::spawner::Spawner;
+
+/// Side effect running an async function of tracked inputs. The tracked
+/// `handler` produces a key, and every new key spawns a run of `func`.
+///
+/// By default a new run cancels the one in flight,
+/// [`AsyncReaction::queued`] makes them run one after another instead.
+#[derive(Clone)]
+pub struct AsyncReaction {
+	body: Arc<AsyncReactionBody>,
+}
+
+pub struct AsyncReactionBody {
+	inner: Mutex<AsyncReactionInner>,
+}
+
+struct AsyncReactionEffect<K, H, F> {
+	keyed: Keyed<K, H>,
+	func: F,
+}
+
+pub trait AsyncReactionEffecty: Send {
+	fn compute(&mut self, cx: &Evaluation) -> u64;
+	fn invoke(&mut self, cancel: CancellationToken) -> BoxFuture<'static, ()>;
+}
+
+impl<K, H, F> AsyncReactionEffecty for AsyncReactionEffect<K, H, F>
+where
+	K: Hash + Send,
+	H: Fn(&Evaluation) -> K + 'static + Send,
+	F: Fn(K, CancellationToken) -> BoxFuture<'static, ()> + 'static + Send,
+{
+	fn compute(&mut self, cx: &Evaluation) -> u64 {
+		self.keyed.compute(cx)
+	}
+
+	fn invoke(&mut self, cancel: CancellationToken) -> BoxFuture<'static, ()> {
+		(self.func)(self.keyed.take(), cancel)
+	}
+}
+
+pub struct AsyncReactionInner {
+	effect: Box<dyn AsyncReactionEffecty>,
+	revision: Option<u64>,
+	/// Cancelled when the reaction is dropped, parent of every run token.
+	root: CancellationToken,
+	/// Token of the latest run.
+	cancel: CancellationToken,
+	/// Whether runs wait for the previous ones.
+	queued: bool,
+	/// Feeds the task running the queued runs one after another.
+	worker: Option<mpsc::UnboundedSender<BoxFuture<'static, ()>>>,
+	spawner: Option<Arc<dyn Spawner>>,
+	node: Node,
+	priority: i32,
+	depth: u32,
+	created: u64,
+	this: Weak<AsyncReactionBody>,
+}
+
+impl Drop for AsyncReactionInner {
+	fn drop(&mut self) {
+		self.root.cancel();
+	}
+}
+
+impl AsyncReaction {
+	#[must_use]
+	pub fn new<K: Hash + Send + 'static>(
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(K, CancellationToken) -> BoxFuture<'static, ()> + 'static + Send,
+	) -> Self {
+		AsyncReaction::new_with_name("<unnamed>", handler, func)
+	}
+
+	/// Same as [`AsyncReaction::new`], named in the reports of its `Var` writes and of batches that do not settle.
+	#[must_use]
+	pub fn new_with_name<K: Hash + Send + 'static>(
+		name: &'static str,
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(K, CancellationToken) -> BoxFuture<'static, ()> + 'static + Send,
+	) -> Self {
+		let root = CancellationToken::new();
+		AsyncReaction {
+			body: Arc::new_cyclic(|this| AsyncReactionBody {
+				inner: Mutex::new(AsyncReactionInner {
+					effect: Box::new(AsyncReactionEffect {
+						keyed: Keyed::new(handler),
+						func,
+					}),
+					revision: None,
+					cancel: root.child_token(),
+					root,
+					queued: false,
+					worker: None,
+					spawner: None,
+					node: Node::new(name, this.clone() as Weak<dyn Derived>),
+					priority: 0,
+					depth: 0,
+					created: Order::next_created(),
+					this: this.clone(),
+				}),
+			}),
+		}
+	}
+
+	/// Lets a new run wait for the previous ones instead of cancelling them.
+	#[must_use]
+	pub fn queued(self) -> Self {
+		self.body.inner.lock().queued = true;
+		self
+	}
+
+	/// Runs futures of this reaction with `spawner` instead of
+	/// the [default](crate::spawner::default) one.
+	#[must_use]
+	pub fn with_spawner(self, spawner: impl Spawner + 'static) -> Self {
+		self.body.inner.lock().spawner = Some(Arc::new(spawner));
+		self
+	}
+
+	/// Runs this reaction before the ones with a lower priority in the same
+	/// batch, whatever their depth. The priority is zero by default.
+	#[must_use]
+	pub fn with_priority(self, priority: i32) -> Self {
+		self.body.inner.lock().priority = priority;
+		self
+	}
+
+	/// Schedules this reaction with `runtime` instead of
+	/// the one that was current when it was created.
+	#[must_use]
+	pub fn with_runtime(self, runtime: &Runtime) -> Self {
+		self.body.inner.lock().node.set_runtime(runtime.clone());
+		self
+	}
+
+	pub fn update(&self) {
+		self.body.update();
+	}
+}
+
+impl AsyncReactionInner {
+	fn spawn(&mut self) {
+		if !self.queued {
+			// a new key makes the previous run stale
+			self.cancel.cancel();
+		}
+
+		let cancel = self.root.child_token();
+		self.cancel = cancel.clone();
+
+		let future = self.effect.invoke(cancel.clone());
+		let run = async move {
+			tokio::select! {
+				_ = cancel.cancelled() => {}
+				_ = future => {}
+			}
+		}
+		.boxed();
+
+		if self.queued {
+			// runs go through one task, so they keep the order they were made in
+			let spawner = self.spawner();
+			let worker = self.worker.get_or_insert_with(|| {
+				let (sender, mut receiver) = mpsc::unbounded::<BoxFuture<'static, ()>>();
+				spawner.spawn(
+					async move {
+						while let Some(run) = receiver.next().await {
+							run.await;
+						}
+					}
+					.boxed(),
+				);
+				sender
+			});
+
+			// the worker only stops once the reaction is dropped
+			let _ = worker.unbounded_send(run);
+		} else {
+			self.spawner().spawn(run);
+		}
+	}
+
+	fn spawner(&self) -> Arc<dyn Spawner> {
+		self.spawner.clone().unwrap_or_else(crate::spawner::default)
+	}
+}
+
+impl Reactive for AsyncReactionBody {
+	fn name(&self) -> &'static str {
+		self.inner.lock().node.name()
+	}
+
+	fn update(&self) {
+		let mut self_mut = self.inner.lock();
+
+		if self_mut.node.is_valid(true) {
+			return;
+		}
+
+		let tracker = Evaluation::new(self_mut.node.this());
+		let addr = derivation::addr(self_mut.this.as_ptr());
+		let revision = derivation::run(Kind::Reaction, self_mut.node.name(), addr, || {
+			self_mut.effect.compute(&tracker)
+		});
+
+		if Some(revision) != self_mut.revision {
+			self_mut.revision = Some(revision);
+			self_mut.spawn();
+		}
+
+		self_mut.node.swap(tracker.take());
+		self_mut.depth = self_mut.node.depth();
+		self_mut.node.set_valid();
+	}
+
+	fn revalidate(&self) {
+		let mut self_mut = self.inner.lock();
+		if self_mut.node.is_invalid() {
+			self_mut.node.set_invalid(Invalid::Maybe);
+		}
+	}
+
+	fn dequeue(&self) {
+		self.inner.lock().node.set_valid();
+	}
+}
+
+impl Derived for AsyncReactionBody {
+	fn invalidate(self: Arc<Self>, invalid: crate::arc::Invalid) {
+		let mut self_mut = self.inner.lock();
+		if !self_mut.node.is_invalid() {
+			if !Runtime::thread_in_batch() {
+				panic!("AsyncReaction was updated outside of the `batch` function");
+			}
+
+			self_mut.node.set_invalid(invalid);
+			let order = Order::new(self_mut.priority, self_mut.depth, self_mut.created);
+			let runtime = self_mut.node.runtime().clone();
+			std::mem::drop(self_mut);
+
+			runtime.enqueue(
+				order,
+				Arc::downgrade(&self) as Weak<dyn Reactive + Send + Sync>,
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+	use crate::arc::{batch, Var};
+
+	fn save(
+		saved: Arc<Mutex<Vec<u32>>>,
+	) -> impl Fn(u32, CancellationToken) -> BoxFuture<'static, ()> {
+		move |value, _| {
+			let saved = saved.clone();
+			async move {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+				saved.lock().push(value);
+			}
+			.boxed()
+		}
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn cancels_previous_run() {
+		let document = Var::new(1);
+		let saved = Arc::new(Mutex::new(Vec::new()));
+
+		let reaction = AsyncReaction::new(
+			{
+				let document = document.clone();
+				move |cx| document.get(cx)
+			},
+			save(saved.clone()),
+		);
+		reaction.update();
+
+		batch(|| document.set(2));
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert_eq!(*saved.lock(), vec![2]);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn queues_runs() {
+		let document = Var::new(1);
+		let saved = Arc::new(Mutex::new(Vec::new()));
+
+		let reaction = AsyncReaction::new(
+			{
+				let document = document.clone();
+				move |cx| document.get(cx)
+			},
+			save(saved.clone()),
+		)
+		.queued();
+		reaction.update();
+
+		batch(|| document.set(2));
+		batch(|| document.set(3));
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(*saved.lock(), vec![1, 2, 3]);
+
+		drop(reaction);
+		batch(|| document.set(4));
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(*saved.lock(), vec![1, 2, 3]);
+	}
+
+	#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+	async fn queues_runs_across_threads() {
+		let document = Var::new(0);
+		let saved = Arc::new(Mutex::new(Vec::new()));
+
+		let reaction = AsyncReaction::new(
+			{
+				let document = document.clone();
+				move |cx| document.get(cx)
+			},
+			{
+				let saved = saved.clone();
+				move |value, _| {
+					let saved = saved.clone();
+					async move {
+						tokio::task::yield_now().await;
+						saved.lock().push(value);
+					}
+					.boxed()
+				}
+			},
+		)
+		.queued();
+		reaction.update();
+
+		for value in 1..50 {
+			batch(|| document.set(value));
+		}
+
+		while saved.lock().len() < 50 {
+			tokio::time::sleep(Duration::from_millis(1)).await;
+		}
+		assert_eq!(*saved.lock(), (0..50).collect::<Vec<_>>());
+	}
+}
@@ -0,0 +1,352 @@
+use std::any::Any;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use parking_lot::{MappedRwLockReadGuard, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use tokio_util::sync::CancellationToken;
+
+use crate::arc::node::{AsyncNode, Fingerprint, Job, Keyed, Node};
+use crate::arc::value::{Access, Value};
+use crate::arc::{Derived, Evaluation};
+use crate::async_state::{AsyncState, Pending};
+use crate::derivation::{self, Kind};
+use crate::spawner::Spawner;
+
+/// Derived value backed by a stream. The tracked `handler` produces a key,
+/// every key gets its own stream, and every item of it becomes the value.
+/// The stream of the previous key is dropped once the key changes.
+pub struct AsyncStream<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	body: Arc<AsyncStreamBody<T>>,
+}
+
+impl<T> Clone for AsyncStream<T>
+where
+	T: Send + Sync + Hash,
+{
+	fn clone(&self) -> Self {
+		Self {
+			body: self.body.clone(),
+		}
+	}
+}
+
+impl<T: Send + Sync + Hash + 'static> From<AsyncStream<T>> for Arc<dyn Any> {
+	fn from(var: AsyncStream<T>) -> Self {
+		var.body
+	}
+}
+
+impl<T: Send + Sync + Hash + 'static> TryFrom<Arc<dyn Any + Send + Sync>> for AsyncStream<T> {
+	type Error = Arc<dyn Any + Send + Sync>;
+	fn try_from(value: Arc<dyn Any + Send + Sync>) -> Result<Self, Self::Error> {
+		Arc::downcast::<AsyncStreamBody<T>>(value).map(|body| AsyncStream { body })
+	}
+}
+
+pub struct AsyncStreamBody<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	value: RwLock<AsyncState<T>>,
+	inner: Mutex<AsyncStreamInner<T>>,
+}
+
+struct StreamEffect<K, H, F> {
+	keyed: Keyed<K, H>,
+	func: F,
+}
+
+pub trait StreamEffecty<T>: Send {
+	fn compute(&mut self, cx: &Evaluation) -> u64;
+	fn subscribe(&mut self) -> BoxStream<'static, T>;
+}
+
+impl<K, T, H, F> StreamEffecty<T> for StreamEffect<K, H, F>
+where
+	K: Hash + Send,
+	H: Fn(&Evaluation) -> K + 'static + Send,
+	F: Fn(K) -> BoxStream<'static, T> + 'static + Send,
+{
+	fn compute(&mut self, cx: &Evaluation) -> u64 {
+		self.keyed.compute(cx)
+	}
+
+	fn subscribe(&mut self) -> BoxStream<'static, T> {
+		(self.func)(self.keyed.take())
+	}
+}
+
+pub struct AsyncStreamInner<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	effect: Box<dyn StreamEffecty<T>>,
+	revision: Option<u64>,
+	fingerprint: Fingerprint,
+	job: Job,
+	node: Node,
+	this: Weak<AsyncStreamBody<T>>,
+}
+
+impl<T> AsyncStream<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	pub fn new<K: Hash + Send + 'static>(
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(K) -> BoxStream<'static, T> + 'static + Send,
+	) -> Self {
+		AsyncStream::new_with_name("<unnamed>", handler, func)
+	}
+
+	/// Same as [`AsyncStream::new`], named in the reports of its `Var` writes.
+	pub fn new_with_name<K: Hash + Send + 'static>(
+		name: &'static str,
+		handler: impl Fn(&Evaluation) -> K + 'static + Send,
+		func: impl Fn(K) -> BoxStream<'static, T> + 'static + Send,
+	) -> Self {
+		AsyncStream {
+			body: Arc::new_cyclic(|this| AsyncStreamBody {
+				value: RwLock::new(AsyncState::Pending),
+				inner: Mutex::new(AsyncStreamInner {
+					effect: Box::new(StreamEffect {
+						keyed: Keyed::new(handler),
+						func,
+					}) as Box<dyn StreamEffecty<T>>,
+					revision: None,
+					fingerprint: Fingerprint::new::<T, std::convert::Infallible>(),
+					job: Job::default(),
+					node: Node::new(name, this.clone() as Weak<dyn Derived>),
+					this: this.clone(),
+				}),
+			}),
+		}
+	}
+
+	/// Polls streams of this value with `spawner` instead of
+	/// the [default](crate::spawner::default) one.
+	pub fn with_spawner(self, spawner: impl Spawner + 'static) -> Self {
+		self.body.inner.lock().job.set_spawner(Arc::new(spawner));
+		self
+	}
+
+	#[inline]
+	pub fn state_once(&self) -> MappedRwLockReadGuard<'_, AsyncState<T>> {
+		self.body.state_once()
+	}
+
+	#[inline]
+	pub fn state<'a>(
+		&'a self,
+		cx: &'a impl AsRef<Evaluation>,
+	) -> MappedRwLockReadGuard<'a, AsyncState<T>> {
+		self.body.state(cx.as_ref())
+	}
+
+	/// The latest item, of the current stream or the previous one.
+	#[inline]
+	pub fn get(&self, cx: &impl AsRef<Evaluation>) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.body.state(cx.as_ref()).value().cloned()
+	}
+
+	/// The latest item, without tracking.
+	#[inline]
+	pub fn get_once(&self) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.body.state_once().value().cloned()
+	}
+
+	/// Same as [`AsyncStream::get`], but fails with [`Pending`]
+	/// until the first item arrives.
+	#[inline]
+	pub fn read(&self, cx: &impl AsRef<Evaluation>) -> Result<T, Pending>
+	where
+		T: Clone,
+	{
+		self.get(cx).ok_or(Pending)
+	}
+}
+
+impl<T> AsyncStreamBody<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	pub fn state_once(&self) -> MappedRwLockReadGuard<'_, AsyncState<T>> {
+		self.inner_update(&mut self.inner.lock());
+		RwLockReadGuard::map(self.value.read(), |v| v)
+	}
+
+	pub fn state(&self, eval: &'_ Evaluation) -> MappedRwLockReadGuard<'_, AsyncState<T>> {
+		{
+			let mut self_mut = self.inner.lock();
+			self.inner_update(&mut self_mut);
+
+			let this = self_mut.this.upgrade().unwrap();
+			let version = self_mut.fingerprint.version();
+			self_mut.node.track(this, version, eval);
+		}
+
+		RwLockReadGuard::map(self.value.read(), |v| v)
+	}
+
+	fn push(&self, cancel: &CancellationToken, item: T) {
+		let mut inner = self.inner.lock();
+
+		// the item belongs to the stream of a previous key
+		if cancel.is_cancelled() {
+			return;
+		}
+
+		if inner
+			.fingerprint
+			.transition(&self.value, |_| AsyncState::Ready(item))
+		{
+			let notification = inner.node.notification();
+			std::mem::drop(inner);
+			notification.send();
+		}
+	}
+}
+
+impl<T> AsyncNode for AsyncStreamBody<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	type Inner = AsyncStreamInner<T>;
+
+	fn lock(&self) -> MutexGuard<'_, AsyncStreamInner<T>> {
+		self.inner.lock()
+	}
+
+	fn node(inner: &mut AsyncStreamInner<T>) -> &mut Node {
+		&mut inner.node
+	}
+
+	fn version(inner: &AsyncStreamInner<T>) -> u64 {
+		inner.fingerprint.version()
+	}
+
+	fn inner_update(&self, inner_mut: &mut AsyncStreamInner<T>) {
+		if inner_mut.node.is_valid(true) {
+			return;
+		}
+
+		let evaluation = Evaluation::new(inner_mut.node.this());
+		let addr = derivation::addr(inner_mut.this.as_ptr());
+		let revision = derivation::run(Kind::Computed, inner_mut.node.name(), addr, || {
+			inner_mut.effect.compute(&evaluation)
+		});
+
+		if Some(revision) != inner_mut.revision {
+			inner_mut.revision = Some(revision);
+
+			// the stream of the previous key is dropped together with its task
+			let cancel = inner_mut.job.restart();
+			inner_mut
+				.fingerprint
+				.transition(&self.value, AsyncState::refreshing);
+
+			let this = inner_mut.this.clone();
+			let stream = inner_mut.effect.subscribe();
+			inner_mut.job.spawn(stream.for_each(move |item| {
+				if let Some(this) = this.upgrade() {
+					this.push(&cancel, item);
+				}
+				futures::future::ready(())
+			}));
+		}
+
+		inner_mut.node.set_valid();
+		inner_mut.node.swap(evaluation.take());
+	}
+}
+
+impl<T> Access<AsyncState<T>> for AsyncStreamBody<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	fn get(&self, tracker: &Evaluation) -> crate::arc::value::Ref<'_, AsyncState<T>> {
+		crate::arc::value::Ref::Guard(self.state(tracker))
+	}
+
+	fn get_once(&self) -> crate::arc::value::Ref<'_, AsyncState<T>> {
+		crate::arc::value::Ref::Guard(self.state_once())
+	}
+}
+
+impl<T> From<AsyncStream<T>> for Value<AsyncState<T>>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	fn from(value: AsyncStream<T>) -> Self {
+		Value::new(value.body)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::channel::mpsc;
+	use futures::executor::LocalPool;
+
+	use super::*;
+	use crate::arc::{Computed, Var};
+	use crate::spawner::LocalPoolSpawner;
+
+	#[test]
+	fn recreates_stream_on_key_change() {
+		let mut pool = LocalPool::new();
+		let service = Var::new("api".to_string());
+		let senders = Arc::new(Mutex::new(Vec::new()));
+
+		let log = AsyncStream::new(
+			{
+				let service = service.clone();
+				move |cx| service.get(cx).clone()
+			},
+			{
+				let senders = senders.clone();
+				move |service: String| {
+					let (sender, receiver) = mpsc::unbounded();
+					senders.lock().push(sender);
+					receiver
+						.map(move |line: &str| format!("{service}: {line}"))
+						.boxed()
+				}
+			},
+		)
+		.with_spawner(LocalPoolSpawner::new(&pool));
+
+		let c = Computed::new(Box::new({
+			let log = log.clone();
+			move |cx| log.get(cx)
+		}));
+
+		assert_eq!(*c.get_once(), None);
+		senders.lock()[0].unbounded_send("started").unwrap();
+		pool.run_until_stalled();
+		assert_eq!(c.get_once().as_deref(), Some("api: started"));
+
+		senders.lock()[0].unbounded_send("ready").unwrap();
+		pool.run_until_stalled();
+		assert_eq!(c.get_once().as_deref(), Some("api: ready"));
+
+		service.set("db".to_string());
+		assert_eq!(c.get_once().as_deref(), Some("api: ready"));
+		pool.run_until_stalled();
+		assert!(senders.lock()[0].is_closed());
+
+		senders.lock()[1].unbounded_send("started").unwrap();
+		pool.run_until_stalled();
+		assert_eq!(c.get_once().as_deref(), Some("db: started"));
+	}
+}
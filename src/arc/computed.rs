@@ -2,14 +2,19 @@ use std::any::Any;
 use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Weak};
 
-use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
+use arc_swap::ArcSwapOption;
+use parking_lot::Mutex;
 
 use crate::arc::addr::WeakAddr;
 use crate::arc::dependencies::Dependencies;
 use crate::arc::value::Access;
-use crate::arc::{Derived, Evaluation, Invalid, Observable, State, Value, Version};
+use crate::arc::{Derived, Evaluation, Executor, Invalid, Observable, State, Value, Version};
+use crate::config;
+use crate::derivation::{self, Kind};
 use crate::hashed::Hashed;
 
 pub struct Computed<T>
@@ -47,18 +52,29 @@ pub struct ComputedBody<T>
 where
 	T: Send + Hash + Sync + 'static,
 {
-	value: RwLock<Option<Hashed<T>>>,
+	name: &'static str,
+	/// Stored by evaluations and shared with readers without locking,
+	/// so that no reader keeps an evaluation waiting.
+	snapshot: ArcSwapOption<Hashed<Arc<T>>>,
+	depth: AtomicU32,
+	/// Locked only briefly, never while locking another node.
+	status: Mutex<Status>,
+	/// Locked for a whole evaluation, see the [lock order](crate::arc#concurrency).
 	inner: Mutex<ComputedInner<T>>,
 }
 
+struct Status {
+	state: State,
+	used_by: BTreeSet<WeakAddr<dyn Derived>>,
+}
+
 pub struct ComputedInner<T>
 where
 	T: Send + Hash + Sync + 'static,
 {
 	func: Box<dyn Fn(&Evaluation) -> T + Send>,
-	state: State,
-	used_by: BTreeSet<WeakAddr<dyn Derived>>,
 	dependencies: Dependencies,
+	executor: Option<Arc<dyn Executor>>,
 	this: Weak<ComputedBody<T>>,
 }
 
@@ -77,28 +93,66 @@ where
 	T: Send + Sync + Hash + 'static,
 {
 	pub fn new(func: Box<dyn Fn(&Evaluation) -> T + Send>) -> Self {
+		Self::new_with_name("<unnamed>", func)
+	}
+
+	pub fn new_with_name(name: &'static str, func: Box<dyn Fn(&Evaluation) -> T + Send>) -> Self {
 		Computed {
 			body: Arc::new_cyclic(|this| ComputedBody {
-				value: RwLock::new(None),
-				inner: Mutex::new(ComputedInner {
-					func,
+				name,
+				snapshot: ArcSwapOption::empty(),
+				depth: AtomicU32::new(0),
+				status: Mutex::new(Status {
 					state: State::Invalid(Invalid::Definitely),
 					used_by: BTreeSet::new(),
+				}),
+				inner: Mutex::new(ComputedInner {
+					func,
 					dependencies: Dependencies::new(),
+					executor: None,
 					this: this.clone(),
 				}),
 			}),
 		}
 	}
 
+	/// Brings the dependencies up to date in parallel with `executor`
+	/// when one of them may have changed, before deciding whether this
+	/// computed evaluates again. Meant for wide aggregations of
+	/// expensive computeds.
+	///
+	/// Only computeds revalidate in parallel, reactions check their
+	/// dependencies one after another. A computed read from a task of an
+	/// executor, such as a nested one, does not use its executor either:
+	/// it holds its lock while it waits, so a bounded pool shared with the
+	/// outer computed could otherwise run out of threads for good.
+	#[must_use]
+	pub fn with_executor(self, executor: impl Executor) -> Self {
+		self.body.inner.lock().executor = Some(Arc::new(executor));
+		self
+	}
+
 	#[inline]
-	pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
-		self.body.get_once()
+	pub fn get_once(&self) -> Arc<T> {
+		self.body.get_arc_once()
 	}
 
+	/// Shares the up to date value. It locks nothing once returned and
+	/// can be kept across evaluations, which store a new value instead.
 	#[inline]
-	pub fn get<'a>(&'a self, cx: &'a impl AsRef<Evaluation>) -> MappedRwLockReadGuard<'a, T> {
-		self.body.get(cx.as_ref())
+	pub fn get(&self, cx: &impl AsRef<Evaluation>) -> Arc<T> {
+		self.body.get_arc(cx.as_ref())
+	}
+
+	/// The same as [`Computed::get`], named after [`Var::get_arc`](crate::arc::Var::get_arc).
+	#[inline]
+	pub fn get_arc(&self, cx: &impl AsRef<Evaluation>) -> Arc<T> {
+		self.body.get_arc(cx.as_ref())
+	}
+
+	#[inline]
+	pub fn get_arc_once(&self) -> Arc<T> {
+		self.body.get_arc_once()
 	}
 }
 
@@ -106,63 +160,82 @@ impl<T> ComputedBody<T>
 where
 	T: Send + Sync + Hash + 'static,
 {
-	pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
+	pub fn get_arc_once(&self) -> Arc<T> {
+		self.warn_untracked();
 		self.update();
-		MappedRwLockReadGuard::map(
-			RwLockReadGuard::map(self.value.read(), |s| s.as_ref().unwrap()),
-			|s| &s.value,
-		)
-	}
-
-	pub fn get<'a>(&'a self, eval: &'_ Evaluation) -> MappedRwLockReadGuard<'a, T> {
-		{
-			let mut self_mut = self.inner.lock();
-			self.inner_update(&mut self_mut);
-			eval.based_on(
-				self_mut.this.upgrade().unwrap(),
-				Version::Hash(self.value.read().as_ref().unwrap().hash),
+		self.snapshot().value.clone()
+	}
+
+	fn warn_untracked(&self) {
+		if config::warn_untracked_reads() && derivation::current().is_none() {
+			tracing::warn!(
+				computed = self.name,
+				"Computed was read outside of any computed or reaction"
 			);
-			self_mut.used_by(eval.parent());
 		}
-		MappedRwLockReadGuard::map(
-			RwLockReadGuard::map(self.value.read(), |s| s.as_ref().unwrap()),
-			|s| &s.value,
-		)
+	}
+
+	pub fn get_arc(&self, eval: &Evaluation) -> Arc<T> {
+		// a write during the update has to reach the reader too
+		self.used_by(eval.parent());
+		let mut self_mut = self.inner.lock();
+		self.inner_update(&mut self_mut);
+		let value = self.snapshot();
+		eval.based_on(self_mut.this.upgrade().unwrap(), Version::Hash(value.hash));
+		value.value.clone()
+	}
+
+	fn snapshot(&self) -> Arc<Hashed<Arc<T>>> {
+		self.snapshot.load_full().unwrap()
 	}
 
 	pub(crate) fn used_by(&self, observable: Weak<dyn Derived>) {
-		self.inner.lock().used_by(observable);
+		self.status.lock().used_by.insert(WeakAddr::new(observable));
 	}
 
 	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		self.inner.lock().not_used_by(derived);
+		self.status
+			.lock()
+			.used_by
+			.remove(&WeakAddr::new(derived.clone()));
 	}
 
 	pub fn inner_update(&self, inner_mut: &mut ComputedInner<T>) {
-		if inner_mut.state == State::Valid {
+		// valid from now on, unless a dependency changes in the meantime
+		let state = std::mem::replace(&mut self.status.lock().state, State::Valid);
+		if state == State::Valid {
 			return;
 		}
 
-		let is_valid = match inner_mut.state {
-			State::Valid => true,
-			State::Invalid(Invalid::Definitely) => false,
-			State::Invalid(Invalid::Maybe) => inner_mut.dependencies.are_valid(),
-		};
+		let result = catch_unwind(AssertUnwindSafe(|| {
+			if state == State::Invalid(Invalid::Maybe) && inner_mut.are_valid() {
+				return;
+			}
 
-		if is_valid {
-			inner_mut.state = State::Valid;
-			return;
+			self.evaluate(inner_mut);
+		}));
+
+		if let Err(payload) = result {
+			self.status.lock().state = State::Invalid(Invalid::Definitely);
+			resume_unwind(payload);
 		}
+	}
 
+	fn evaluate(&self, inner_mut: &mut ComputedInner<T>) {
 		let this = inner_mut.this.clone() as Weak<dyn Derived>;
 		let evaluation = Evaluation::new(this);
-		let value = (inner_mut.func)(&evaluation);
-		inner_mut.state = State::Valid;
+		let addr = derivation::addr(inner_mut.this.as_ptr());
+		let value = derivation::run(Kind::Computed, self.name, addr, || {
+			(inner_mut.func)(&evaluation)
+		});
 
 		let parent = inner_mut.this.clone() as Weak<dyn Derived>;
 		inner_mut.dependencies.swap(evaluation.take(), &parent);
+		self.depth
+			.store(inner_mut.dependencies.depth(), Ordering::Relaxed);
 
-		*self.value.write() = Some(Hashed::new(value));
+		self.snapshot
+			.store(Some(Arc::new(Hashed::new(Arc::new(value)))));
 	}
 }
 
@@ -170,12 +243,26 @@ impl<T> ComputedInner<T>
 where
 	T: Send + Sync + Hash + 'static,
 {
-	pub(crate) fn used_by(&mut self, observable: Weak<dyn Derived>) {
-		self.used_by.insert(WeakAddr::new(observable));
+	fn are_valid(&self) -> bool {
+		match &self.executor {
+			Some(executor) => self.dependencies.are_valid_in(executor.as_ref()),
+			None => self.dependencies.are_valid(),
+		}
 	}
+}
 
-	fn not_used_by(&mut self, derived: &Weak<dyn Derived>) {
-		self.used_by.remove(&WeakAddr::new(derived.clone()));
+impl Status {
+	/// Live observers, forgetting the dropped ones.
+	fn observers(&mut self) -> Vec<Arc<dyn Derived>> {
+		let mut observers = Vec::with_capacity(self.used_by.len());
+		self.used_by.retain(|item| match item.upgrade() {
+			Some(item) => {
+				observers.push(item);
+				true
+			}
+			None => false,
+		});
+		observers
 	}
 }
 
@@ -189,7 +276,7 @@ where
 	}
 
 	fn version(&self) -> Version {
-		Version::Hash(self.value.read().as_ref().unwrap().hash)
+		Version::Hash(self.snapshot.load().as_ref().unwrap().hash)
 	}
 
 	fn used_by(&self, derived: Weak<dyn Derived>) {
@@ -199,6 +286,10 @@ where
 	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
 		ComputedBody::not_used_by(self, derived)
 	}
+
+	fn depth(&self) -> u32 {
+		self.depth.load(Ordering::Relaxed)
+	}
 }
 
 impl<T> Access<T> for ComputedBody<T>
@@ -206,11 +297,11 @@ where
 	T: Send + Sync + Hash + 'static,
 {
 	fn get(&self, tracker: &Evaluation) -> crate::arc::value::Ref<'_, T> {
-		crate::arc::value::Ref::Guard(self.get(tracker))
+		crate::arc::value::Ref::Shared(self.get_arc(tracker))
 	}
 
 	fn get_once(&self) -> crate::arc::value::Ref<'_, T> {
-		crate::arc::value::Ref::Guard(self.get_once())
+		crate::arc::value::Ref::Shared(self.get_arc_once())
 	}
 }
 
@@ -219,17 +310,18 @@ where
 	T: Send + Sync + Hash + 'static,
 {
 	fn invalidate(self: Arc<Self>, invalid: crate::arc::Invalid) {
-		let mut self_mut = self.inner.lock();
-		if matches!(self_mut.state, State::Valid) {
-			self_mut.state = State::Invalid(invalid);
-			self_mut.used_by.retain(|item| {
-				if let Some(item) = item.upgrade() {
-					item.invalidate(Invalid::Maybe);
-					true
-				} else {
-					false
-				}
-			});
+		let observers = {
+			let mut status = self.status.lock();
+			if !matches!(status.state, State::Valid) {
+				return;
+			}
+
+			status.state = State::Invalid(invalid);
+			status.observers()
+		};
+
+		for observer in observers {
+			observer.invalidate(Invalid::Maybe);
 		}
 	}
 }
@@ -2,20 +2,14 @@ use std::collections::BTreeMap;
 use std::sync::{Arc, Weak};
 
 use crate::arc::addr::ArcAddr;
-use crate::arc::{Derived, Observable, Version};
+use crate::arc::executor;
+use crate::arc::{Derived, Executor, Observable, Task, Version};
 
+#[derive(Default)]
 pub struct Dependencies {
 	based_on: BTreeMap<ArcAddr<dyn Observable>, Version>,
 }
 
-impl Default for Dependencies {
-	fn default() -> Self {
-		Dependencies {
-			based_on: BTreeMap::new(),
-		}
-	}
-}
-
 impl Dependencies {
 	pub fn new() -> Self {
 		Self {
@@ -24,8 +18,8 @@ impl Dependencies {
 	}
 
 	pub fn drop(&mut self, parent: &Weak<dyn Derived>) {
-		for (item, _) in &self.based_on {
-			item.not_used_by(&parent)
+		for item in self.based_on.keys() {
+			item.not_used_by(parent)
 		}
 	}
 
@@ -43,12 +37,47 @@ impl Dependencies {
 		true
 	}
 
+	/// Like [`Dependencies::are_valid`], but brings every dependency up to
+	/// date with `executor` first, so independent ones evaluate in parallel.
+	/// They all evaluate even when the first one already changed.
+	///
+	/// Called from a task of an executor, it checks them one after another
+	/// instead: the lock of the node is held meanwhile, and waiting for a
+	/// pool the task is part of could wait forever.
+	pub fn are_valid_in(&self, executor: &dyn Executor) -> bool {
+		if self.based_on.len() > 1 && !executor::is_worker() {
+			let tasks = self
+				.based_on
+				.keys()
+				.map(|base| {
+					let base = Arc::clone(base);
+					Box::new(move || {
+						executor::as_worker(|| base.update());
+					}) as Task
+				})
+				.collect();
+
+			executor.run(tasks);
+		}
+
+		self.are_valid()
+	}
+
+	/// One more than the deepest dependency, zero without any.
+	pub fn depth(&self) -> u32 {
+		self.based_on
+			.keys()
+			.map(|base| base.depth() + 1)
+			.max()
+			.unwrap_or(0)
+	}
+
 	pub fn swap(&mut self, next: Dependencies, parent: &Weak<dyn Derived>) {
 		let prev = std::mem::replace(&mut self.based_on, next.based_on);
 
 		// Diff the keys
 		prev.keys()
 			.filter(|k| !self.based_on.contains_key(k))
-			.for_each(|k| k.not_used_by(&parent));
+			.for_each(|k| k.not_used_by(parent));
 	}
 }
@@ -43,4 +43,9 @@ impl Evaluation {
 	pub fn take(self) -> Dependencies {
 		self.inner.into_inner().dependencies
 	}
+
+	/// Takes the dependencies collected so far out of a shared evaluation.
+	pub(crate) fn take_dependencies(&self) -> Dependencies {
+		std::mem::take(&mut self.inner.lock().dependencies)
+	}
 }
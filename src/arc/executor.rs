@@ -0,0 +1,310 @@
+use std::cell::Cell;
+
+use parking_lot::Mutex;
+
+use crate::arc::Task;
+
+/// Runs independent work in parallel: the reactions of a batch round with
+/// [`Runtime::with_executor`](crate::arc::Runtime::with_executor), or the
+/// dependencies of a computed with
+/// [`Computed::with_executor`](crate::arc::Computed::with_executor).
+///
+/// Work started by a task runs on the thread of that task, without the
+/// executor, so a bounded pool never waits for itself.
+///
+/// Any `Fn(Vec<Task>)` is an executor, which is the way to use a thread
+/// pool such as rayon:
+///
+/// ```ignore
+/// runtime.with_executor(|tasks: Vec<Task>| {
+///     rayon::scope(|scope| tasks.into_iter().for_each(|task| scope.spawn(|_| task())))
+/// })
+/// ```
+pub trait Executor: Send + Sync + 'static {
+	/// Runs every task, returning once all of them finished.
+	fn run(&self, tasks: Vec<Task>);
+}
+
+impl<F> Executor for F
+where
+	F: Fn(Vec<Task>) + Send + Sync + 'static,
+{
+	fn run(&self, tasks: Vec<Task>) {
+		self(tasks)
+	}
+}
+
+thread_local! {
+	static WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `func` as a task of an executor.
+pub(crate) fn as_worker<R>(func: impl FnOnce() -> R) -> R {
+	struct Restore(bool);
+
+	impl Drop for Restore {
+		fn drop(&mut self) {
+			WORKER.with(|worker| worker.set(self.0));
+		}
+	}
+
+	let _restore = Restore(WORKER.with(|worker| worker.replace(true)));
+	func()
+}
+
+/// Whether this thread runs a task of an executor.
+pub(crate) fn is_worker() -> bool {
+	WORKER.with(|worker| worker.get())
+}
+
+/// Runs the tasks on scoped threads, as many as the available parallelism.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Threads;
+
+impl Executor for Threads {
+	fn run(&self, tasks: Vec<Task>) {
+		let workers = std::thread::available_parallelism()
+			.map_or(1, |workers| workers.get())
+			.min(tasks.len());
+		let tasks = Mutex::new(tasks.into_iter());
+
+		std::thread::scope(|scope| {
+			for _ in 0..workers {
+				scope.spawn(|| loop {
+					let Some(task) = tasks.lock().next() else {
+						break;
+					};
+					task();
+				});
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	use std::sync::mpsc;
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	use super::*;
+	use crate::arc::{Computed, Reaction, Runtime, Var};
+	use crate::config;
+
+	/// A thread per task, whatever the available parallelism.
+	fn spawned(tasks: Vec<Task>) {
+		std::thread::scope(|scope| {
+			for task in tasks {
+				scope.spawn(task);
+			}
+		})
+	}
+
+	#[test]
+	fn runs_a_round_in_parallel() {
+		let runtime = Runtime::new().with_executor(spawned);
+		let input = Var::new(0);
+		let output = Var::new(0);
+		let met = Arc::new(Mutex::new(Vec::new()));
+
+		// each one only finishes in time if the other one runs meanwhile
+		let (first, second) = (mpsc::channel(), mpsc::channel());
+		let waiting = [(first.0, second.1), (second.0, first.1)].map(|(sender, receiver)| {
+			let (input, output, met) = (input.clone(), output.clone(), met.clone());
+			let receiver = Mutex::new(receiver);
+			let reaction = Reaction::new_in(
+				&runtime,
+				Box::new(move |cx| {
+					let value = input.get(cx);
+					if value > 0 {
+						sender.send(()).unwrap();
+						let timeout = Duration::from_secs(5);
+						met.lock()
+							.push(receiver.lock().recv_timeout(timeout).is_ok());
+						output.set(value);
+					}
+				}),
+			);
+			reaction.update();
+			reaction
+		});
+
+		// queued by the parallel round, so it runs in the next one
+		let seen = Arc::new(Mutex::new(Vec::new()));
+		let follower = Reaction::new_in(
+			&runtime,
+			Box::new({
+				let (output, seen) = (output.clone(), seen.clone());
+				move |cx| seen.lock().push(output.get(cx))
+			}),
+		);
+		follower.update();
+
+		runtime.batch(|| input.set(1));
+		assert_eq!(*met.lock(), vec![true, true]);
+		assert_eq!(*seen.lock(), vec![0, 1]);
+		drop(waiting);
+	}
+
+	/// One worker thread shared by every caller, like a bounded pool.
+	fn single_worker() -> impl Fn(Vec<Task>) + Send + Sync + Clone {
+		let (sender, receiver) = mpsc::channel::<Task>();
+		std::thread::spawn(move || receiver.into_iter().for_each(|task| task()));
+		let sender = Arc::new(Mutex::new(sender));
+
+		move |tasks: Vec<Task>| {
+			let (done, finished) = mpsc::channel();
+			let count = tasks.len();
+			for task in tasks {
+				let done = done.clone();
+				let task = Box::new(move || {
+					task();
+					done.send(()).unwrap();
+				});
+				sender.lock().send(task).unwrap();
+			}
+			(0..count).for_each(|_| finished.recv().unwrap());
+		}
+	}
+
+	#[test]
+	fn nested_computeds_share_a_bounded_pool() {
+		let pool = single_worker();
+		let (a, b) = (Var::new(0), Var::new(0));
+		let (a_copy, b_copy) = (a.map(|a| *a), b.map(|b| *b));
+
+		// may have changed after a write, so they revalidate with the pool
+		let parts = [1, 2].map(|factor| {
+			let (a, b) = (a_copy.clone(), b_copy.clone());
+			Computed::new(Box::new(move |cx| (*a.get(cx) + *b.get(cx)) * factor))
+				.with_executor(pool.clone())
+		});
+
+		let total = Computed::new(Box::new(move |cx| {
+			parts.iter().map(|part| *part.get(cx)).sum::<i32>()
+		}))
+		.with_executor(pool);
+		assert_eq!(*total.get_once(), 0);
+
+		// the parts revalidate on the only worker, without waiting for it
+		a.set(1);
+		assert_eq!(*total.get_once(), 3);
+	}
+
+	#[test]
+	fn threads_run_every_task() {
+		let done = Arc::new(Mutex::new(Vec::new()));
+		let tasks = (0..8)
+			.map(|index| {
+				let done = done.clone();
+				Box::new(move || done.lock().push(index)) as Task
+			})
+			.collect();
+
+		Threads.run(tasks);
+		done.lock().sort();
+		assert_eq!(*done.lock(), (0..8).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn hands_back_reactions_of_other_runtimes() {
+		let runtime = Runtime::new().with_executor(spawned);
+		let other = Runtime::new();
+		let input = Var::new(0);
+		let output = Var::new(0);
+		let current = Arc::new(Mutex::new(Vec::new()));
+
+		let writers = [0, 1].map(|_| {
+			let (input, output, current) = (input.clone(), output.clone(), current.clone());
+			let reaction = Reaction::new_in(
+				&runtime,
+				Box::new(move |cx| {
+					let value = input.get(cx);
+					current.lock().push(Runtime::current().in_batch());
+					output.set(value);
+				}),
+			);
+			reaction.update();
+			reaction
+		});
+
+		let seen = Arc::new(Mutex::new(Vec::new()));
+		let reader = Reaction::new_in(
+			&other,
+			Box::new({
+				let (output, seen) = (output.clone(), seen.clone());
+				move |cx| seen.lock().push(output.get(cx))
+			}),
+		);
+		reader.update();
+		current.lock().clear();
+
+		runtime.batch(|| input.set(1));
+		assert_eq!(*seen.lock(), vec![0, 1]);
+		assert_eq!(*current.lock(), vec![true; 2]);
+		drop((writers, reader));
+	}
+
+	#[test]
+	fn reports_writes_of_parallel_rounds() {
+		let runtime = Runtime::new().with_executor(Threads);
+		let x = Var::new_with_name("x", 0);
+
+		// both run in each round, on the threads of the executor
+		let reactions = ["a", "b"].map(|name| {
+			let (x, runtime) = (x.clone(), runtime.clone());
+			let reaction = Reaction::new_with_name_in(
+				&runtime.clone(),
+				name,
+				Box::new(move |cx| {
+					let value = x.get(cx);
+					if value > 0 {
+						runtime.batch(|| x.set(value + 1));
+					}
+				}),
+			);
+			reaction.update();
+			reaction
+		});
+
+		let payload = catch_unwind(AssertUnwindSafe(|| runtime.batch(|| x.set(1)))).unwrap_err();
+		let message = payload.downcast::<String>().unwrap();
+		let last = config::config().max_rounds;
+		assert!(message.contains(&format!(r#"round {last}: ran ["a", "b"], changed ["x"]"#)));
+		drop(reactions);
+	}
+
+	#[test]
+	fn revalidates_dependencies_in_parallel() {
+		let input = Var::new(0);
+		let met = Arc::new(Mutex::new(Vec::new()));
+
+		// each one only finishes in time if the other one evaluates meanwhile
+		let (first, second) = (mpsc::channel(), mpsc::channel());
+		let parts = [(first.0, second.1), (second.0, first.1)].map(|(sender, receiver)| {
+			let (input, met) = (input.clone(), met.clone());
+			let receiver = Mutex::new(receiver);
+			Computed::new(Box::new(move |cx| {
+				let value = input.get(cx);
+				if value > 0 {
+					sender.send(()).unwrap();
+					let timeout = Duration::from_secs(5);
+					met.lock()
+						.push(receiver.lock().recv_timeout(timeout).is_ok());
+				}
+				value
+			}))
+		});
+
+		let total = Computed::new(Box::new(move |cx| {
+			parts.iter().map(|part| *part.get(cx)).sum::<i32>()
+		}))
+		.with_executor(spawned);
+		assert_eq!(*total.get_once(), 0);
+
+		input.set(1);
+		assert_eq!(*total.get_once(), 2);
+		assert_eq!(*met.lock(), vec![true, true]);
+	}
+}
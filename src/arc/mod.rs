@@ -1,12 +1,45 @@
+//! The thread safe graph.
+//!
+//! # Concurrency
+//!
+//! Any thread can read, write and evaluate nodes at the same time without
+//! deadlocking, as long as computeds do not write to `Var`s:
+//!
+//! - A computed or reaction holds its own lock for a whole evaluation, and
+//!   only waits for the locks of the nodes it reads, which are below it in
+//!   the graph. These waits go in one direction, so they cannot form a cycle.
+//! - Invalidating a node only takes short locks, never held while another
+//!   node is locked, so writes never wait for an evaluation to finish.
+//! - A node becomes valid when its evaluation starts. A write during the
+//!   evaluation invalidates it again, so the change is never missed.
+//! - A reaction that is already running is not waited for. It runs again
+//!   once done, on the thread that ran it.
+//!
+//! The guards returned by [`Var::get_ref`] lock the value: a thread holding
+//! one can block writers of that `Var`, so they are meant to be dropped right
+//! away. [`Var::get_arc`] and [`Computed::get`] return snapshots instead,
+//! which can be kept. A snapshot shares the `Arc` of the value, which writes
+//! and evaluations then replace rather than change. Reading it locks nothing,
+//! only taking the first one of a `Var` after a write takes the read lock.
+
 mod addr;
 mod r#async;
 mod async2;
+mod async_reaction;
+mod async_stream;
 mod batch;
 mod computed;
 mod r#const;
 mod dependencies;
 mod evaluation;
+mod executor;
+mod mutation;
+mod node;
+mod query;
 mod reaction;
+mod runtime;
+mod scheduler;
+mod transaction;
 mod value;
 mod var;
 
@@ -15,13 +48,21 @@ mod microtask;
 
 use std::sync::{Arc, Weak};
 
-pub use batch::{batch, batch_microtask, in_batch};
+pub use crate::async_state::{AsyncState, Attempt, NotReady, Pending};
+pub use async_reaction::AsyncReaction;
+pub use async_stream::AsyncStream;
+pub use batch::{batch, batch_microtask, in_batch, transaction};
 pub use computed::Computed;
 pub use dependencies::Dependencies;
 pub use evaluation::Evaluation;
+pub use executor::{Executor, Threads};
+pub use mutation::Mutation;
+pub use query::{IntoQueryKey, QueryClient, QueryKey};
 pub use r#async::Async;
-pub use r#async2::Async as Async2;
-pub use reaction::{Reaction, Reactions, Reactive, CHANGED};
+pub use r#async2::{Async as Async2, AsyncContext};
+pub use reaction::{Reaction, Reactions, Reactive};
+pub use runtime::Runtime;
+pub use scheduler::{Frame, Pinned, Scheduler, Spawned, Task, Timed};
 pub use value::Value;
 pub use var::Var;
 
@@ -45,6 +86,12 @@ pub trait Observable: 'static + Send + Sync {
 	/// Notify this observable that `derived` stopped
 	/// to listen.
 	fn not_used_by(&self, derived: &Weak<dyn Derived>);
+
+	/// Distance from the sources of the graph, as of the
+	/// last evaluation. Reactions run in order of depth.
+	fn depth(&self) -> u32 {
+		0
+	}
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
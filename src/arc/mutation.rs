@@ -0,0 +1,223 @@
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::arc::{IntoQueryKey, QueryClient, QueryKey, Runtime, Var};
+
+/// Optimistic change of several `Var`s backed by an async action.
+///
+/// The changes are applied in one batch before the action starts. If the
+/// action fails, every `Var` that was not changed again in the meantime gets
+/// its previous value back. If it succeeds, the related queries are invalidated.
+pub struct Mutation {
+	updates: Vec<Box<dyn Optimistic>>,
+	invalidates: Vec<(QueryClient, QueryKey)>,
+	runtime: Runtime,
+	/// Set while the action runs, so that dropping
+	/// the future of [`Mutation::run`] rolls back too.
+	pending: bool,
+}
+
+type Apply<T> = Box<dyn FnOnce(&Var<T>) -> (Arc<T>, u64) + Send>;
+
+trait Optimistic: Send {
+	fn apply(&mut self);
+	fn rollback(&mut self);
+}
+
+struct Update<T: 'static> {
+	var: Var<T>,
+	func: Option<Apply<T>>,
+	original: Option<Arc<T>>,
+	hash: u64,
+}
+
+impl<T> Optimistic for Update<T>
+where
+	T: Send + Sync + Hash + 'static,
+{
+	fn apply(&mut self) {
+		if let Some(func) = self.func.take() {
+			let (original, hash) = func(&self.var);
+			self.original = Some(original);
+			self.hash = hash;
+		}
+	}
+
+	fn rollback(&mut self) {
+		if let Some(original) = self.original.take() {
+			// a newer change wins over the rollback
+			self.var.replace_if(self.hash, original);
+		}
+	}
+}
+
+impl Default for Mutation {
+	fn default() -> Self {
+		Mutation::new()
+	}
+}
+
+impl Mutation {
+	pub fn new() -> Self {
+		Mutation {
+			updates: Vec::new(),
+			invalidates: Vec::new(),
+			runtime: Runtime::current(),
+			pending: false,
+		}
+	}
+
+	/// Optimistically sets `var` to `value`.
+	pub fn set<T>(self, var: &Var<T>, value: T) -> Self
+	where
+		T: Send + Sync + Hash + 'static,
+	{
+		self.with(var, move |var| var.swap_with(move |_| value))
+	}
+
+	/// Optimistically changes `var` in place.
+	pub fn update<T>(self, var: &Var<T>, func: impl FnOnce(&mut T) + Send + 'static) -> Self
+	where
+		T: Send + Sync + Hash + Clone + 'static,
+	{
+		self.with(var, move |var| {
+			var.swap_with(move |current| {
+				let mut value = T::clone(current);
+				func(&mut value);
+				value
+			})
+		})
+	}
+
+	/// Invalidates the queries of `client` starting with `prefix`
+	/// once the action succeeded.
+	pub fn invalidates(mut self, client: &QueryClient, prefix: impl IntoQueryKey) -> Self {
+		self.invalidates.push((client.clone(), prefix.query_key()));
+		self
+	}
+
+	/// Applies the optimistic changes and runs `action`. The changes are
+	/// rolled back as well when the returned future is dropped before the
+	/// action finished.
+	pub async fn run<R, E>(mut self, action: impl Future<Output = Result<R, E>>) -> Result<R, E> {
+		self.runtime.batch(|| {
+			for update in &mut self.updates {
+				update.apply();
+			}
+		});
+		self.pending = true;
+
+		let result = action.await;
+
+		match &result {
+			Ok(_) => {
+				self.pending = false;
+				for (client, prefix) in &self.invalidates {
+					client.invalidate(prefix.clone());
+				}
+			}
+			Err(_) => self.rollback(),
+		}
+
+		result
+	}
+
+	fn rollback(&mut self) {
+		self.pending = false;
+		self.runtime.batch(|| {
+			for update in self.updates.iter_mut().rev() {
+				update.rollback();
+			}
+		});
+	}
+
+	fn with<T>(
+		mut self,
+		var: &Var<T>,
+		func: impl FnOnce(&Var<T>) -> (Arc<T>, u64) + Send + 'static,
+	) -> Self
+	where
+		T: Send + Sync + Hash + 'static,
+	{
+		self.updates.push(Box::new(Update {
+			var: var.clone(),
+			func: Some(Box::new(func)),
+			original: None,
+			hash: 0,
+		}));
+		self
+	}
+}
+
+impl Drop for Mutation {
+	fn drop(&mut self) {
+		if self.pending {
+			self.rollback();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use super::*;
+	use crate::arc::AsyncState;
+
+	#[tokio::test]
+	async fn rolls_back_on_failure() {
+		let todos = Var::new(vec!["a"]);
+		let count = Var::new(1);
+
+		let result = Mutation::new()
+			.update(&todos, |todos| todos.push("b"))
+			.set(&count, 2)
+			.run(async {
+				assert_eq!(todos.get_once(), vec!["a", "b"]);
+				assert_eq!(count.get_once(), 2);
+				count.set(3);
+				Err::<(), _>("offline")
+			})
+			.await;
+
+		assert_eq!(result, Err("offline"));
+		assert_eq!(todos.get_once(), vec!["a"]);
+		// changed while the action was running
+		assert_eq!(count.get_once(), 3);
+	}
+
+	#[tokio::test]
+	async fn rolls_back_when_cancelled() {
+		let count = Var::new(1);
+
+		let run = Mutation::new()
+			.set(&count, 2)
+			.run(futures::future::pending::<Result<(), ()>>());
+
+		let timeout = tokio::time::timeout(std::time::Duration::from_millis(1), run).await;
+		assert!(timeout.is_err());
+		assert_eq!(count.get_once(), 1);
+	}
+
+	#[tokio::test]
+	async fn invalidates_on_success() {
+		let client = QueryClient::new();
+		let value = Var::new(1);
+
+		let query = client.query(|_| ("value",), |_| Box::pin(async { Ok::<_, ()>(1) }));
+		let _ = query.state_once();
+		tokio::task::yield_now().await;
+		assert!(query.state_once().is_ready());
+
+		Mutation::new()
+			.set(&value, 2)
+			.invalidates(&client, ("value",))
+			.run(async { Ok::<_, ()>(()) })
+			.await
+			.unwrap();
+
+		assert_eq!(value.get_once(), 2);
+		assert_eq!(*query.state_once(), AsyncState::Refreshing(Arc::new(1)));
+	}
+}
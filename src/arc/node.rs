@@ -0,0 +1,354 @@
+//! Parts shared by the async nodes: the key their runs are made for, the
+//! task producing their value, its version, and their side of the graph.
+
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+
+use futures::future::AbortHandle;
+use futures::FutureExt;
+use parking_lot::{MutexGuard, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use crate::arc::addr::WeakAddr;
+use crate::arc::dependencies::Dependencies;
+use crate::arc::{Derived, Evaluation, Invalid, Observable, Runtime, State, Version};
+use crate::async_state::AsyncState;
+use crate::hashed::Hashed;
+use crate::spawner::Spawner;
+
+/// Key produced by the tracked handler of an async node.
+pub(crate) struct Keyed<K, H> {
+	handler: H,
+	key: Option<Hashed<K>>,
+}
+
+impl<K, H> Keyed<K, H>
+where
+	K: Hash,
+	H: Fn(&Evaluation) -> K,
+{
+	pub(crate) fn new(handler: H) -> Self {
+		Keyed { handler, key: None }
+	}
+
+	/// Runs the handler and returns the hash of the new key.
+	pub(crate) fn compute(&mut self, cx: &Evaluation) -> u64 {
+		let key = Hashed::new((self.handler)(cx));
+		let hash = key.hash;
+		self.key = Some(key);
+		hash
+	}
+
+	/// The latest key, a run may be started for it more than once.
+	pub(crate) fn key(&self) -> &K {
+		&self.key.as_ref().unwrap().value
+	}
+
+	/// Takes the latest key for the only run made for it.
+	pub(crate) fn take(&mut self) -> K {
+		self.key.take().unwrap().value
+	}
+}
+
+/// The task running the future or stream of an async node.
+#[derive(Default)]
+pub(crate) struct Job {
+	cancel: CancellationToken,
+	handle: Option<AbortHandle>,
+	spawner: Option<Arc<dyn Spawner>>,
+}
+
+impl Job {
+	pub(crate) fn set_spawner(&mut self, spawner: Arc<dyn Spawner>) {
+		self.spawner = Some(spawner);
+	}
+
+	/// Token of the latest run.
+	pub(crate) fn token(&self) -> CancellationToken {
+		self.cancel.clone()
+	}
+
+	/// Cancels the latest run and returns the token of the next one.
+	pub(crate) fn restart(&mut self) -> CancellationToken {
+		self.abort();
+		self.cancel = CancellationToken::new();
+		self.cancel.clone()
+	}
+
+	pub(crate) fn spawn(&mut self, future: impl Future<Output = ()> + Send + 'static) {
+		let (future, handle) = futures::future::abortable(future);
+		self.handle = Some(handle);
+		self.spawner().spawn(future.map(|_| ()).boxed());
+	}
+
+	/// The spawner of this node, or the [default](crate::spawner::default) one.
+	pub(crate) fn spawner(&self) -> Arc<dyn Spawner> {
+		self.spawner.clone().unwrap_or_else(crate::spawner::default)
+	}
+
+	/// Cancels the token handed to the running future and aborts its task.
+	pub(crate) fn abort(&mut self) {
+		self.cancel.cancel();
+		if let Some(handle) = self.handle.take() {
+			handle.abort();
+		}
+	}
+
+	/// Forgets the task once it finished on its own.
+	pub(crate) fn finish(&mut self) {
+		self.handle = None;
+	}
+
+	pub(crate) fn is_running(&self) -> bool {
+		self.handle.is_some()
+	}
+}
+
+impl Drop for Job {
+	fn drop(&mut self) {
+		self.abort();
+	}
+}
+
+/// Version of the [`AsyncState`] of a node. Errors are not required
+/// to be hashable, so every failure counts as a new version.
+pub(crate) struct Fingerprint {
+	version: u64,
+	failures: u64,
+}
+
+impl Fingerprint {
+	pub(crate) fn new<T: Hash, E>() -> Self {
+		Fingerprint {
+			version: AsyncState::<T, E>::Pending.fingerprint(0),
+			failures: 0,
+		}
+	}
+
+	pub(crate) fn version(&self) -> u64 {
+		self.version
+	}
+
+	/// Moves the stored state forward and returns whether its version changed.
+	pub(crate) fn transition<T: Hash, E>(
+		&mut self,
+		value: &RwLock<AsyncState<T, E>>,
+		func: impl FnOnce(AsyncState<T, E>) -> AsyncState<T, E>,
+	) -> bool {
+		let mut value = value.write();
+		*value = func(std::mem::take(&mut *value));
+
+		if matches!(*value, AsyncState::Failed(..) | AsyncState::Retrying(..)) {
+			self.failures += 1;
+		}
+
+		let version = value.fingerprint(self.failures);
+		let changed = version != self.version;
+		self.version = version;
+		changed
+	}
+}
+
+/// Graph side of an async node: whether it is up to date,
+/// what it was derived from and who observes it.
+pub(crate) struct Node {
+	/// Name shown when the node writes a `Var` while it evaluates.
+	name: &'static str,
+	state: State,
+	used_by: BTreeSet<WeakAddr<dyn Derived>>,
+	dependencies: Dependencies,
+	/// Depth as of the last evaluation.
+	depth: u32,
+	runtime: Runtime,
+	this: Weak<dyn Derived>,
+}
+
+impl Node {
+	pub(crate) fn new(name: &'static str, this: Weak<dyn Derived>) -> Self {
+		Node {
+			name,
+			state: State::Invalid(Invalid::Definitely),
+			used_by: BTreeSet::new(),
+			dependencies: Dependencies::new(),
+			depth: 0,
+			runtime: Runtime::current(),
+			this,
+		}
+	}
+
+	pub(crate) fn name(&self) -> &'static str {
+		self.name
+	}
+
+	pub(crate) fn this(&self) -> Weak<dyn Derived> {
+		self.this.clone()
+	}
+
+	pub(crate) fn runtime(&self) -> &Runtime {
+		&self.runtime
+	}
+
+	pub(crate) fn set_runtime(&mut self, runtime: Runtime) {
+		self.runtime = runtime;
+	}
+
+	/// Distance from the sources of the graph, as of the last evaluation.
+	pub(crate) fn depth(&self) -> u32 {
+		self.depth
+	}
+
+	pub(crate) fn is_invalid(&self) -> bool {
+		self.state != State::Valid
+	}
+
+	/// Whether the node is up to date. Checks the dependencies when they
+	/// may have changed and `check` allows it, marking the node valid if
+	/// they did not.
+	pub(crate) fn is_valid(&mut self, check: bool) -> bool {
+		let is_valid = match self.state {
+			State::Valid => true,
+			State::Invalid(Invalid::Definitely) => false,
+			State::Invalid(Invalid::Maybe) => check && self.dependencies.are_valid(),
+		};
+
+		if is_valid {
+			self.state = State::Valid;
+		}
+
+		is_valid
+	}
+
+	pub(crate) fn set_valid(&mut self) {
+		self.state = State::Valid;
+	}
+
+	pub(crate) fn set_invalid(&mut self, invalid: Invalid) {
+		self.state = State::Invalid(invalid);
+	}
+
+	/// Replaces what the node was derived from.
+	pub(crate) fn swap(&mut self, dependencies: Dependencies) {
+		self.dependencies.swap(dependencies, &self.this);
+		self.depth = self.dependencies.depth();
+	}
+
+	/// Records that `eval` read `version` of the node `this`.
+	pub(crate) fn track(&mut self, this: Arc<dyn Observable>, version: u64, eval: &Evaluation) {
+		eval.based_on(this, Version::Hash(version));
+		self.used_by(eval.parent());
+	}
+
+	pub(crate) fn used_by(&mut self, derived: Weak<dyn Derived>) {
+		self.used_by.insert(WeakAddr::new(derived));
+	}
+
+	pub(crate) fn not_used_by(&mut self, derived: &Weak<dyn Derived>) {
+		self.used_by.remove(&WeakAddr::new(derived.clone()));
+	}
+
+	/// Live observers, forgetting the dropped ones.
+	pub(crate) fn observers(&mut self) -> Vec<Arc<dyn Derived>> {
+		let mut observers = Vec::with_capacity(self.used_by.len());
+		self.used_by.retain(|item| match item.upgrade() {
+			Some(item) => {
+				observers.push(item);
+				true
+			}
+			None => false,
+		});
+		observers
+	}
+
+	/// Observers to notify after the state changed outside of an update,
+	/// once the lock of the node is released.
+	pub(crate) fn notification(&mut self) -> Notification {
+		Notification {
+			observers: self.observers(),
+			runtime: self.runtime.clone(),
+		}
+	}
+}
+
+impl Drop for Node {
+	fn drop(&mut self) {
+		self.dependencies.drop(&self.this);
+	}
+}
+
+/// Observers of a node whose state changed.
+pub(crate) struct Notification {
+	observers: Vec<Arc<dyn Derived>>,
+	runtime: Runtime,
+}
+
+impl Notification {
+	pub(crate) fn send(self) {
+		// only invalidating deps, not the value itself,
+		// reactions re-read it once the lock is released
+		self.runtime.batch(|| {
+			for item in self.observers {
+				item.invalidate(Invalid::Maybe);
+			}
+		});
+	}
+}
+
+/// An async node, that is the body behind its lock.
+/// Its [`Observable`] and [`Derived`] sides are the same for all of them.
+pub(crate) trait AsyncNode: Send + Sync + 'static {
+	type Inner: Send;
+
+	fn lock(&self) -> MutexGuard<'_, Self::Inner>;
+
+	fn node(inner: &mut Self::Inner) -> &mut Node;
+
+	fn version(inner: &Self::Inner) -> u64;
+
+	/// Brings the node up to date, starting a run when its inputs changed.
+	fn inner_update(&self, inner: &mut Self::Inner);
+}
+
+impl<N: AsyncNode> Observable for N {
+	fn update(&self) -> Version {
+		let mut inner = self.lock();
+		self.inner_update(&mut inner);
+		Version::Hash(N::version(&inner))
+	}
+
+	fn version(&self) -> Version {
+		Version::Hash(N::version(&self.lock()))
+	}
+
+	fn used_by(&self, derived: Weak<dyn Derived>) {
+		N::node(&mut self.lock()).used_by(derived);
+	}
+
+	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
+		N::node(&mut self.lock()).not_used_by(derived);
+	}
+
+	fn depth(&self) -> u32 {
+		N::node(&mut self.lock()).depth()
+	}
+}
+
+impl<N: AsyncNode> Derived for N {
+	fn invalidate(self: Arc<Self>, invalid: Invalid) {
+		let observers = {
+			let mut inner = self.lock();
+			let node = N::node(&mut inner);
+			if node.is_invalid() {
+				return;
+			}
+
+			node.set_invalid(invalid);
+			node.observers()
+		};
+
+		for observer in observers {
+			observer.invalidate(Invalid::Maybe);
+		}
+	}
+}
@@ -0,0 +1,486 @@
+use std::any::{Any, TypeId};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use parking_lot::Mutex;
+use smallvec::SmallVec;
+use tokio::time::Instant;
+
+use crate::arc::{Async, Evaluation, Runtime, Var};
+use crate::hashed::Hashed;
+
+type Erased = Arc<dyn Any + Send + Sync>;
+type ErasedFuture = Shared<BoxFuture<'static, Result<Erased, Erased>>>;
+
+/// Key of a query: a path of hashed segments, so that related
+/// queries can be invalidated together by a common prefix.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QueryKey(SmallVec<[u64; 4]>);
+
+impl QueryKey {
+	pub fn new() -> Self {
+		QueryKey::default()
+	}
+
+	pub fn with(mut self, segment: impl Hash) -> Self {
+		self.0.push(Hashed::new(segment).hash);
+		self
+	}
+
+	pub fn starts_with(&self, prefix: &QueryKey) -> bool {
+		self.0.starts_with(&prefix.0)
+	}
+}
+
+/// Inputs of a query that can be turned into its [`QueryKey`].
+/// Every element of a tuple becomes one segment.
+pub trait IntoQueryKey {
+	fn query_key(&self) -> QueryKey;
+}
+
+impl IntoQueryKey for QueryKey {
+	fn query_key(&self) -> QueryKey {
+		self.clone()
+	}
+}
+
+impl<A: Hash> IntoQueryKey for (A,) {
+	fn query_key(&self) -> QueryKey {
+		QueryKey::new().with(&self.0)
+	}
+}
+
+impl<A: Hash, B: Hash> IntoQueryKey for (A, B) {
+	fn query_key(&self) -> QueryKey {
+		QueryKey::new().with(&self.0).with(&self.1)
+	}
+}
+
+impl<A: Hash, B: Hash, C: Hash> IntoQueryKey for (A, B, C) {
+	fn query_key(&self) -> QueryKey {
+		QueryKey::new().with(&self.0).with(&self.1).with(&self.2)
+	}
+}
+
+impl<A: Hash, B: Hash, C: Hash, D: Hash> IntoQueryKey for (A, B, C, D) {
+	fn query_key(&self) -> QueryKey {
+		QueryKey::new()
+			.with(&self.0)
+			.with(&self.1)
+			.with(&self.2)
+			.with(&self.3)
+	}
+}
+
+/// Cache shared by async queries. Queries with the same key run one
+/// future at a time and share its result.
+///
+/// A result is fresh for the stale time, later reads fetch it again.
+/// Entries nobody asked for during the cache time are dropped.
+/// Times are measured with tokio time.
+#[derive(Clone)]
+pub struct QueryClient {
+	body: Arc<QueryClientBody>,
+}
+
+struct QueryClientBody {
+	runtime: Runtime,
+	inner: Mutex<QueryClientInner>,
+}
+
+struct QueryClientInner {
+	stale_time: Duration,
+	cache_time: Duration,
+	/// Keyed by the types of the result too, so that
+	/// one key used with different types never mixes them.
+	entries: BTreeMap<(QueryKey, TypeId), QueryEntry>,
+}
+
+struct QueryEntry {
+	generation: u64,
+	/// Bumped together with `generation`, so that only
+	/// the queries of an invalidated key fetch again.
+	epoch: Var<u64>,
+	value: Option<Erased>,
+	fetched_at: Instant,
+	last_used: Instant,
+	stale: bool,
+	in_flight: Option<ErasedFuture>,
+}
+
+impl Default for QueryClient {
+	fn default() -> Self {
+		QueryClient::new()
+	}
+}
+
+impl QueryClient {
+	pub fn new() -> Self {
+		QueryClient {
+			body: Arc::new(QueryClientBody {
+				runtime: Runtime::current(),
+				inner: Mutex::new(QueryClientInner {
+					stale_time: Duration::ZERO,
+					cache_time: Duration::from_secs(5 * 60),
+					entries: BTreeMap::new(),
+				}),
+			}),
+		}
+	}
+
+	/// How long a result is served without fetching it again. Zero by default.
+	pub fn with_stale_time(self, stale_time: Duration) -> Self {
+		self.body.inner.lock().stale_time = stale_time;
+		self
+	}
+
+	/// How long a result is kept after it was last requested. Five minutes by default.
+	pub fn with_cache_time(self, cache_time: Duration) -> Self {
+		self.body.inner.lock().cache_time = cache_time;
+		self
+	}
+
+	/// Creates an `Async` whose runs go through this cache. The tracked `handler`
+	/// produces the inputs of the query, `func` fetches the value for them.
+	/// Queries re-run when their key gets [invalidated](QueryClient::invalidate).
+	pub fn query<K, T, E>(
+		&self,
+		handler: impl Fn(&Evaluation) -> K + Send + 'static,
+		func: impl Fn(&K) -> BoxFuture<'static, Result<T, E>> + Send + 'static,
+	) -> Async<Arc<T>, E>
+	where
+		K: IntoQueryKey + Hash + Send + 'static,
+		T: Send + Sync + Hash + 'static,
+		E: Clone + Send + Sync + 'static,
+	{
+		let client = self.clone();
+		let fetcher = self.clone();
+
+		Async::new_fallible(
+			move |cx| {
+				let key = handler(cx);
+				let generation = client.generation::<T, E>(key.query_key(), cx);
+				(key, generation)
+			},
+			move |(key, _), _| fetcher.fetch(key.query_key(), || func(key)),
+		)
+	}
+
+	/// Resolves with the fresh cached value of `key`, joins its running
+	/// fetch or starts a new one with `func`.
+	pub fn fetch<T, E>(
+		&self,
+		key: QueryKey,
+		func: impl FnOnce() -> BoxFuture<'static, Result<T, E>>,
+	) -> BoxFuture<'static, Result<Arc<T>, E>>
+	where
+		T: Send + Sync + 'static,
+		E: Clone + Send + Sync + 'static,
+	{
+		let mut inner = self.body.inner.lock();
+		let now = Instant::now();
+		inner.collect(now);
+
+		let stale_time = inner.stale_time;
+		let entry = inner.entry::<T, E>(key.clone(), now);
+		entry.last_used = now;
+
+		if let Some(value) = &entry.value {
+			if !entry.stale && entry.fetched_at + stale_time > now {
+				let value = downcast::<T>(value.clone());
+				return futures::future::ready(Ok(value)).boxed();
+			}
+		}
+
+		let shared = match &entry.in_flight {
+			Some(shared) => shared.clone(),
+			None => {
+				let future = func();
+				let body = Arc::downgrade(&self.body);
+				let generation = entry.generation;
+
+				let shared = async move {
+					let result = future
+						.await
+						.map(|value| Arc::new(value) as Erased)
+						.map_err(|error| Arc::new(error) as Erased);
+
+					complete::<T, E>(&body, key, generation, &result);
+					result
+				}
+				.boxed()
+				.shared();
+
+				entry.in_flight = Some(shared.clone());
+				shared
+			}
+		};
+
+		std::mem::drop(inner);
+
+		async move {
+			match shared.await {
+				Ok(value) => Ok(downcast::<T>(value)),
+				Err(error) => Err(E::clone(&downcast::<E>(error))),
+			}
+		}
+		.boxed()
+	}
+
+	/// Marks the results of every key starting with `prefix` as stale
+	/// and makes their queries fetch again. A fetch that is still running
+	/// is not started twice: the queries join it, then fetch again once
+	/// it is done.
+	pub fn invalidate(&self, prefix: impl IntoQueryKey) {
+		let prefix = prefix.query_key();
+
+		let epochs: Vec<_> = {
+			let mut inner = self.body.inner.lock();
+			inner
+				.entries
+				.iter_mut()
+				.filter(|((key, _), _)| key.starts_with(&prefix))
+				.map(|(_, entry)| {
+					entry.generation += 1;
+					entry.stale = true;
+					entry.epoch.clone()
+				})
+				.collect()
+		};
+
+		// the generations were bumped under the lock, the epochs
+		// only wake up the queries, which read them from the entries
+		self.body.runtime.batch(|| {
+			for epoch in epochs {
+				epoch.update(|epoch| *epoch += 1);
+			}
+		});
+	}
+
+	fn generation<T: 'static, E: 'static>(&self, key: QueryKey, cx: &Evaluation) -> u64 {
+		let epoch = {
+			let mut inner = self.body.inner.lock();
+			inner
+				.entry::<T, E>(key.clone(), Instant::now())
+				.epoch
+				.clone()
+		};
+
+		// read without the client lock, which writers of the epoch take too,
+		// and before the generation, so that a later invalidation reaches us
+		epoch.get(cx);
+
+		let mut inner = self.body.inner.lock();
+		inner.entry::<T, E>(key, Instant::now()).generation
+	}
+}
+
+impl QueryClientInner {
+	fn entry<T: 'static, E: 'static>(&mut self, key: QueryKey, now: Instant) -> &mut QueryEntry {
+		self.entries
+			.entry((key, TypeId::of::<(T, E)>()))
+			.or_insert_with(|| QueryEntry {
+				generation: 0,
+				epoch: Var::new(0),
+				value: None,
+				fetched_at: now,
+				last_used: now,
+				stale: false,
+				in_flight: None,
+			})
+	}
+
+	/// Drops the entries that were not requested during the cache time,
+	/// unless a query still waits for their invalidation.
+	fn collect(&mut self, now: Instant) {
+		let cache_time = self.cache_time;
+		self.entries.retain(|_, entry| {
+			entry.in_flight.is_some()
+				|| entry.last_used + cache_time > now
+				|| entry.epoch.is_observed()
+		});
+	}
+}
+
+fn complete<T: 'static, E: 'static>(
+	body: &Weak<QueryClientBody>,
+	key: QueryKey,
+	generation: u64,
+	result: &Result<Erased, Erased>,
+) {
+	let Some(body) = body.upgrade() else {
+		return;
+	};
+
+	let mut inner = body.inner.lock();
+	let Some(entry) = inner.entries.get_mut(&(key, TypeId::of::<(T, E)>())) else {
+		return;
+	};
+
+	entry.in_flight = None;
+	if let Ok(value) = result {
+		entry.value = Some(value.clone());
+		entry.fetched_at = Instant::now();
+	}
+
+	if entry.generation == generation {
+		entry.stale &= result.is_err();
+		return;
+	}
+
+	// invalidated while fetching, so the queries that joined it fetch again
+	entry.generation += 1;
+	let epoch = entry.epoch.clone();
+	std::mem::drop(inner);
+	body.runtime.batch(|| epoch.update(|epoch| *epoch += 1));
+}
+
+fn downcast<T: Send + Sync + 'static>(value: Erased) -> Arc<T> {
+	Arc::downcast::<T>(value).expect("query entries are keyed by their types")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::arc::{AsyncState, Computed};
+
+	fn fetcher(runs: Arc<Mutex<u32>>) -> impl Fn(u32) -> BoxFuture<'static, Result<u32, String>> {
+		move |id: u32| {
+			*runs.lock() += 1;
+			async move {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+				Ok(id * 10)
+			}
+			.boxed()
+		}
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn dedupes_and_invalidates() {
+		let client = QueryClient::new();
+		let runs = Arc::new(Mutex::new(0));
+		let fetch = Arc::new(fetcher(runs.clone()));
+
+		let user = Var::new(1u32);
+		let queries: Vec<_> = (0..3)
+			.map(|_| {
+				let (user, fetch) = (user.clone(), fetch.clone());
+				client.query(move |cx| ("user", user.get(cx)), move |&(_, id)| fetch(id))
+			})
+			.collect();
+
+		let c = Computed::new(Box::new({
+			let queries = queries.clone();
+			move |cx| {
+				queries
+					.iter()
+					.map(|query| query.get(cx).map(|value| *value))
+					.collect::<Vec<_>>()
+			}
+		}));
+
+		assert_eq!(*c.get_once(), vec![None, None, None]);
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert_eq!(*c.get_once(), vec![Some(10); 3]);
+		assert_eq!(*runs.lock(), 1);
+
+		let first = queries[0].get_once().unwrap();
+		assert!(Arc::ptr_eq(&first, &queries[1].get_once().unwrap()));
+
+		client.invalidate(("post",));
+		let _ = c.get_once();
+		assert_eq!(*runs.lock(), 1);
+
+		client.invalidate(("user",));
+		assert_eq!(*queries[0].state_once(), AsyncState::Refreshing(first));
+		let _ = c.get_once();
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert_eq!(*c.get_once(), vec![Some(10); 3]);
+		assert_eq!(*runs.lock(), 2);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn joins_a_fetch_invalidated_in_flight() {
+		let client = QueryClient::new();
+		let runs = Arc::new(Mutex::new(0));
+		let fetch = fetcher(runs.clone());
+		let query = client.query(|_| ("user", 1), move |&(_, id)| fetch(id));
+
+		let c = Computed::new(Box::new({
+			let query = query.clone();
+			move |cx| query.get(cx).map(|value| *value)
+		}));
+
+		let _ = c.get_once();
+		client.invalidate(("user",));
+		let _ = c.get_once();
+		assert_eq!(*runs.lock(), 1);
+
+		tokio::time::sleep(Duration::from_millis(15)).await;
+		let _ = c.get_once();
+		assert_eq!(*runs.lock(), 2);
+
+		tokio::time::sleep(Duration::from_millis(15)).await;
+		assert_eq!(*c.get_once(), Some(10));
+		assert_eq!(*runs.lock(), 2);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn serves_fresh_results() {
+		let client = QueryClient::new().with_stale_time(Duration::from_secs(1));
+		let runs = Arc::new(Mutex::new(0));
+		let fetch = fetcher(runs.clone());
+
+		let key = QueryKey::new().with("user").with(1);
+		assert_eq!(*client.fetch(key.clone(), || fetch(1)).await.unwrap(), 10);
+		assert_eq!(*client.fetch(key.clone(), || fetch(1)).await.unwrap(), 10);
+		assert_eq!(*runs.lock(), 1);
+
+		tokio::time::sleep(Duration::from_secs(2)).await;
+		assert_eq!(*client.fetch(key, || fetch(1)).await.unwrap(), 10);
+		assert_eq!(*runs.lock(), 2);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn scopes_invalidation_by_key_and_type() {
+		let client = QueryClient::new();
+		let reads = Arc::new(Mutex::new(0));
+
+		let user = client.query(
+			{
+				let reads = reads.clone();
+				move |_| {
+					*reads.lock() += 1;
+					("user", 1)
+				}
+			},
+			|_| async { Ok::<_, String>(10u32) }.boxed(),
+		);
+		let name = client.query(
+			|_| ("user", 1),
+			|_| async { Ok::<_, String>("ann") }.boxed(),
+		);
+
+		let c = Computed::new(Box::new({
+			let (user, name) = (user.clone(), name.clone());
+			move |cx| (user.get(cx).map(|v| *v), name.get(cx).map(|v| *v))
+		}));
+
+		let _ = c.get_once();
+		tokio::time::sleep(Duration::from_millis(1)).await;
+		assert_eq!(*c.get_once(), (Some(10), Some("ann")));
+		assert_eq!(*reads.lock(), 1);
+
+		client.invalidate(("post",));
+		let _ = c.get_once();
+		assert_eq!(*reads.lock(), 1);
+
+		client.invalidate(("user",));
+		let _ = c.get_once();
+		assert_eq!(*reads.lock(), 2);
+	}
+}
@@ -1,16 +1,62 @@
+use std::cmp::Reverse;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
 
 use parking_lot::Mutex;
 
-use crate::arc::batch::in_batch;
 use crate::arc::dependencies::Dependencies;
-use crate::arc::{Derived, Evaluation, Invalid, State};
+use crate::arc::{Derived, Evaluation, Invalid, Runtime, Scheduler, State};
+use crate::derivation::{self, Kind};
 
 pub trait Reactive {
 	fn update(&self);
+
+	/// Name shown when reactions do not settle.
+	fn name(&self) -> &'static str {
+		"<unnamed>"
+	}
+
+	/// Called when the changes that invalidated it were undone. It stays
+	/// queued, and its [`Reactive::update`] only runs it if a dependency
+	/// still changed.
+	fn revalidate(&self) {}
+
+	/// Called when a batch dropped it from its queue because the reactions
+	/// did not settle. It forgets the pending run, so that the next change
+	/// of a dependency queues it again.
+	fn dequeue(&self) {}
+}
+
+/// Position of a reaction in the queue of a batch: higher priorities
+/// run first, then shallower reactions, then the ones created earlier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Order {
+	priority: Reverse<i32>,
+	depth: u32,
+	created: u64,
 }
 
-pub static mut CHANGED: Mutex<Vec<Weak<dyn Reactive>>> = Mutex::new(vec![]);
+static CREATED: AtomicU64 = AtomicU64::new(0);
+
+impl Order {
+	pub(crate) fn new(priority: i32, depth: u32, created: u64) -> Self {
+		Order {
+			priority: Reverse(priority),
+			depth,
+			created,
+		}
+	}
+
+	pub(crate) fn priority(&self) -> i32 {
+		self.priority.0
+	}
+
+	/// Creation counter of reactions, to break ties between equal depths.
+	pub(crate) fn next_created() -> u64 {
+		CREATED.fetch_add(1, Ordering::Relaxed)
+	}
+}
 
 #[derive(Default, Clone)]
 pub struct Reactions<const N: usize> {
@@ -41,18 +87,33 @@ pub struct Reaction {
 
 pub struct ReactionBody {
 	pub(crate) inner: Mutex<ReactionInner>,
+	/// Locked for a whole run, see the [lock order](crate::arc#concurrency).
+	eval: Mutex<ReactionEval>,
+	/// Set when the reaction has to run again once the current run ends.
+	stale: AtomicBool,
 }
 
 pub struct ReactionInner {
 	state: State,
-	#[allow(unused)]
 	pub(crate) name: &'static str,
+	priority: i32,
+	depth: u32,
+	created: u64,
+	/// Whether a batch queued it and did not run it yet, so that it is
+	/// queued once per batch whatever the number of changed inputs.
+	queued: bool,
+	scheduler: Option<Arc<dyn Scheduler>>,
+	runtime: Runtime,
+	this: Weak<ReactionBody>,
+}
+
+struct ReactionEval {
 	func: Box<dyn Fn(&Evaluation) + Send>,
 	dependencies: Dependencies,
 	this: Weak<ReactionBody>,
 }
 
-impl Drop for ReactionInner {
+impl Drop for ReactionEval {
 	fn drop(&mut self) {
 		let refr = self.this.clone() as Weak<dyn Derived>;
 		self.dependencies.drop(&refr)
@@ -67,79 +128,198 @@ impl Reaction {
 
 	#[must_use]
 	pub fn new_with_name(name: &'static str, func: Box<dyn Fn(&Evaluation) + Send>) -> Self {
+		Self::new_with_name_in(&Runtime::current(), name, func)
+	}
+
+	/// Creates a reaction scheduled by `runtime` instead of the current one.
+	#[must_use]
+	pub fn new_in(runtime: &Runtime, func: Box<dyn Fn(&Evaluation) + Send>) -> Self {
+		Self::new_with_name_in(runtime, "<unnamed>", func)
+	}
+
+	#[must_use]
+	pub fn new_with_name_in(
+		runtime: &Runtime,
+		name: &'static str,
+		func: Box<dyn Fn(&Evaluation) + Send>,
+	) -> Self {
 		Reaction {
 			body: Arc::new_cyclic(|this| ReactionBody {
-				inner: Mutex::new(ReactionInner {
+				stale: AtomicBool::new(false),
+				eval: Mutex::new(ReactionEval {
 					func,
+					dependencies: Dependencies::new(),
+					this: this.clone(),
+				}),
+				inner: Mutex::new(ReactionInner {
 					name,
 					state: State::Invalid(Invalid::Definitely),
-					dependencies: Dependencies::new(),
+					priority: 0,
+					depth: 0,
+					created: Order::next_created(),
+					queued: false,
+					scheduler: None,
+					runtime: runtime.clone(),
 					this: this.clone(),
 				}),
 			}),
 		}
 	}
 
+	/// Runs this reaction before the ones with a lower priority in the same
+	/// batch, whatever their depth. The priority is zero by default.
+	#[must_use]
+	pub fn with_priority(self, priority: i32) -> Self {
+		self.body.inner.lock().priority = priority;
+		self
+	}
+
+	/// Lets `scheduler` decide when this reaction runs after a batch
+	/// invalidated it. [`Reaction::update`] still runs it right away.
+	#[must_use]
+	pub fn with_scheduler(self, scheduler: impl Scheduler) -> Self {
+		self.body.inner.lock().scheduler = Some(Arc::new(scheduler));
+		self
+	}
+
 	pub fn update_unchecked(&self) {
-		// NOTE: this logic is shared with the Self::update
+		let mut eval = self.body.eval.lock();
+		self.body.inner.lock().state = State::Valid;
+		self.body.evaluate(&mut eval);
+	}
 
-		let mut self_mut = self.body.inner.lock();
+	pub fn update(&self) {
+		self.body.run();
+	}
+}
+
+impl ReactionBody {
+	fn run(&self) {
+		// a run in progress, on this thread or another one, runs it again once done
+		self.stale.store(true, Ordering::SeqCst);
+		let Some(mut eval) = self.eval.try_lock() else {
+			return;
+		};
+		self.stale.store(false, Ordering::SeqCst);
+
+		// valid from now on, unless a dependency changes in the meantime
+		let state = std::mem::replace(&mut self.inner.lock().state, State::Valid);
+		let result = catch_unwind(AssertUnwindSafe(|| {
+			let is_valid = match state {
+				State::Valid => true,
+				State::Invalid(Invalid::Definitely) => false,
+				State::Invalid(Invalid::Maybe) => eval.dependencies.are_valid(),
+			};
+
+			if !is_valid {
+				self.evaluate(&mut eval);
+			}
+		}));
+		std::mem::drop(eval);
+
+		if let Err(payload) = result {
+			self.inner.lock().state = State::Invalid(Invalid::Definitely);
+			resume_unwind(payload);
+		}
 
-		let this = Arc::downgrade(&self.body) as Weak<dyn Derived>;
+		if self.stale.swap(false, Ordering::SeqCst) {
+			self.rerun();
+		}
+	}
+
+	fn evaluate(&self, eval: &mut ReactionEval) {
+		let this = eval.this.clone() as Weak<dyn Derived>;
 		let tracker = Evaluation::new(this.clone());
-		(self_mut.func)(&tracker);
+		let name = self.inner.lock().name;
+		let addr = derivation::addr(eval.this.as_ptr());
+		derivation::run(Kind::Reaction, name, addr, || (eval.func)(&tracker));
 
-		self_mut.dependencies.swap(tracker.take(), &this);
-		self_mut.state = State::Valid;
+		eval.dependencies.swap(tracker.take(), &this);
+		self.inner.lock().depth = eval.dependencies.depth();
 	}
 
-	pub fn update(&self) {
-		self.body.update();
+	/// Queues the reaction again, because it changed one of its own
+	/// dependencies or was invalidated during its run.
+	fn rerun(&self) {
+		let (order, runtime, this) = {
+			let mut self_mut = self.inner.lock();
+			self_mut.state = State::Invalid(Invalid::Definitely);
+			self_mut.queued = true;
+			let order = Order::new(self_mut.priority, self_mut.depth, self_mut.created);
+			(order, self_mut.runtime.clone(), self_mut.this.clone())
+		};
+
+		runtime.batch(|| runtime.enqueue(order, this as Weak<dyn Reactive + Send + Sync>));
 	}
 }
 
 impl Reactive for ReactionBody {
+	fn name(&self) -> &'static str {
+		self.inner.lock().name
+	}
 	fn update(&self) {
-		let mut self_mut = self.inner.lock();
+		let (scheduler, runtime, this) = {
+			let mut self_mut = self.inner.lock();
+			self_mut.queued = false;
+			let Some(scheduler) = self_mut.scheduler.clone() else {
+				std::mem::drop(self_mut);
+				return self.run();
+			};
 
-		let is_valid = match self_mut.state {
-			State::Valid => true,
-			State::Invalid(Invalid::Definitely) => false,
-			State::Invalid(Invalid::Maybe) => self_mut.dependencies.are_valid(),
+			(scheduler, self_mut.runtime.clone(), self_mut.this.clone())
 		};
 
-		if is_valid {
-			self_mut.state = State::Valid;
-			return;
-		}
-
-		// NOTE: this logic is shared with the Self::update_unchecked
+		scheduler.schedule(Box::new(move || {
+			if let Some(body) = this.upgrade() {
+				runtime.batch(|| body.run());
+			}
+		}));
+	}
 
-		let this = self_mut.this.clone() as Weak<dyn Derived>;
-		let tracker = Evaluation::new(this.clone());
-		(self_mut.func)(&tracker);
+	fn revalidate(&self) {
+		let mut self_mut = self.inner.lock();
+		if let State::Invalid(_) = self_mut.state {
+			self_mut.state = State::Invalid(Invalid::Maybe);
+		}
+	}
 
-		self_mut.dependencies.swap(tracker.take(), &this);
+	fn dequeue(&self) {
+		let mut self_mut = self.inner.lock();
+		self_mut.queued = false;
 		self_mut.state = State::Valid;
 	}
 }
 
 impl Derived for ReactionBody {
 	fn invalidate(self: Arc<Self>, invalid: crate::arc::Invalid) {
+		// the reaction writes to one of its own dependencies
+		if derivation::is_running(derivation::addr(Arc::as_ptr(&self))) {
+			self.stale.store(true, Ordering::SeqCst);
+			return;
+		}
+
 		let mut self_mut = self.inner.lock();
-		if matches!(self_mut.state, State::Valid) {
-			if !in_batch() {
+		if self_mut.state == State::Valid {
+			if !Runtime::thread_in_batch() {
 				panic!("Reaction was updated outside of the `batch` function");
 			}
 
 			self_mut.state = State::Invalid(invalid);
+		} else if self_mut.scheduler.is_none() || !Runtime::thread_in_batch() {
+			// already waiting for its run, which sees this change too
+			return;
+		}
+
+		// a scheduler hears of every batch that changes the inputs
+		if !std::mem::replace(&mut self_mut.queued, true) {
+			let order = Order::new(self_mut.priority, self_mut.depth, self_mut.created);
+			let runtime = self_mut.runtime.clone();
 			std::mem::drop(self_mut);
 
-			unsafe {
-				CHANGED
-					.lock()
-					.push(Arc::downgrade(&self) as Weak<dyn Reactive>)
-			}
+			runtime.enqueue(
+				order,
+				Arc::downgrade(&self) as Weak<dyn Reactive + Send + Sync>,
+			);
 		}
 	}
 }
@@ -0,0 +1,422 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+
+use parking_lot::Mutex;
+
+use crate::arc::reaction::Order;
+use crate::arc::{executor, transaction};
+use crate::arc::{Executor, Reactive, Task};
+use crate::config;
+use crate::rounds::{self, Rounds};
+
+/// Owner of the batch state and the reaction queue.
+///
+/// Nodes that schedule reactions or open batches remember the runtime
+/// that was [current](Runtime::current) when they were created, so graphs
+/// created in different runtimes never run each other's reactions.
+/// Batches are tracked per thread inside a runtime, and reactions run on
+/// the thread whose batch invalidated them.
+///
+/// `Var`s and computeds are not bound to a runtime. A write in a batch of
+/// one runtime that reaches a reaction of another one queues it in its own
+/// runtime, which runs it once the outermost batch of the thread ends.
+#[derive(Clone)]
+pub struct Runtime {
+	body: Arc<RuntimeBody>,
+}
+
+struct RuntimeBody {
+	/// Key of the batches of this runtime on each thread.
+	id: u64,
+	executor: Mutex<Option<Arc<dyn Executor>>>,
+}
+
+#[derive(Default)]
+struct ThreadBatch {
+	started: bool,
+	microtask: bool,
+	/// Reactions to run, each one at most once.
+	changed: BTreeMap<Order, Queued>,
+	/// Number of reactions queued so far, to tell apart the ones
+	/// queued by a transaction.
+	queued: u64,
+}
+
+struct Queued {
+	reactive: Weak<dyn Reactive + Send + Sync>,
+	seq: u64,
+}
+
+impl ThreadBatch {
+	fn is_idle(&self) -> bool {
+		!self.started && !self.microtask && self.changed.is_empty()
+	}
+
+	fn enqueue(&mut self, order: Order, reactive: Weak<dyn Reactive + Send + Sync>) {
+		let seq = self.queued;
+		self.queued += 1;
+		self.changed
+			.entry(order)
+			.or_insert(Queued { reactive, seq });
+	}
+}
+
+static GLOBAL: OnceLock<Runtime> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+	static CURRENT: RefCell<Vec<Runtime>> = const { RefCell::new(Vec::new()) };
+	/// Batches of this thread, by runtime.
+	static BATCHES: RefCell<HashMap<u64, ThreadBatch>> = RefCell::new(HashMap::new());
+	/// Runtimes with reactions queued by a batch of another runtime.
+	static DEFERRED: RefCell<Vec<Runtime>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Default for Runtime {
+	fn default() -> Self {
+		Runtime::new()
+	}
+}
+
+impl Runtime {
+	pub fn new() -> Self {
+		Runtime {
+			body: Arc::new(RuntimeBody {
+				id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+				executor: Mutex::new(None),
+			}),
+		}
+	}
+
+	/// Runs the reactions of each round of a batch with `executor`, so that
+	/// independent reactions run in parallel. Reactions of a higher priority
+	/// still finish before the next ones start, but reactions of the same
+	/// priority no longer run in order of depth. The reactions they queue
+	/// run in the next round, on the thread of the batch.
+	pub fn with_executor(self, executor: impl Executor) -> Self {
+		*self.body.executor.lock() = Some(Arc::new(executor));
+		self
+	}
+
+	/// Runtime of the nodes created outside of [`Runtime::enter`].
+	pub fn global() -> Runtime {
+		GLOBAL.get_or_init(Runtime::new).clone()
+	}
+
+	/// The innermost entered runtime of this thread, or the global one.
+	pub fn current() -> Runtime {
+		CURRENT
+			.with(|current| current.borrow().last().cloned())
+			.unwrap_or_else(Runtime::global)
+	}
+
+	/// Makes this runtime current on this thread while `func` runs.
+	pub fn enter<R>(&self, func: impl FnOnce() -> R) -> R {
+		struct Exit;
+
+		impl Drop for Exit {
+			fn drop(&mut self) {
+				CURRENT.with(|current| current.borrow_mut().pop());
+			}
+		}
+
+		CURRENT.with(|current| current.borrow_mut().push(self.clone()));
+		let _exit = Exit;
+		func()
+	}
+
+	pub fn in_batch(&self) -> bool {
+		self.with_thread(|batch| batch.started)
+	}
+
+	/// Whether any runtime has a batch open on this thread.
+	pub(crate) fn thread_in_batch() -> bool {
+		BATCHES.with(|batches| batches.borrow().values().any(|batch| batch.started))
+	}
+
+	/// Runs `func` in a batch of this runtime on this thread. The reactions it
+	/// invalidated run once the outermost batch ends. If `func` panics, the
+	/// batch is closed and they stay queued for the next one.
+	pub fn batch(&self, func: impl FnOnce()) {
+		let is_root = self.batch_start();
+		{
+			let _stop = is_root.then(|| Stop(self));
+			func();
+		}
+		if is_root {
+			self.batch_end();
+		}
+	}
+
+	/// Runs `func` in a batch that is undone if it returns an error or panics.
+	///
+	/// Every `Var` written by `func` gets its original value back on abort.
+	/// The reactions it invalidated stay queued until the outermost batch
+	/// ends, but then only run if one of their dependencies still changed.
+	/// A transaction nested in another one is undone on its own, but its
+	/// changes are undone with the enclosing one too.
+	pub fn transaction<R, E>(&self, func: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+		let is_root = self.batch_start();
+		let queued = self.with_thread(|batch| batch.queued);
+
+		transaction::begin();
+		let result = panic::catch_unwind(AssertUnwindSafe(func));
+		let commit = matches!(result, Ok(Ok(_)));
+		transaction::end(commit);
+
+		if !commit {
+			let aborted: Vec<_> = self.with_thread(|batch| {
+				batch
+					.changed
+					.values()
+					.filter(|item| item.seq >= queued)
+					.map(|item| item.reactive.clone())
+					.collect()
+			});
+
+			for reaction in aborted {
+				if let Some(reactive) = reaction.upgrade() {
+					reactive.revalidate();
+				}
+			}
+		}
+
+		if is_root {
+			self.batch_end();
+		}
+
+		match result {
+			Ok(result) => result,
+			Err(payload) => panic::resume_unwind(payload),
+		}
+	}
+
+	#[cfg(target_arch = "wasm32")]
+	pub fn batch_microtask(&self, func: impl FnOnce()) {
+		let is_root = self.batch_start();
+		let is_first_microtask =
+			self.with_thread(|batch| !std::mem::replace(&mut batch.microtask, true));
+		{
+			let _stop = is_root.then(|| Stop(self));
+			func();
+		}
+
+		if is_first_microtask {
+			let this = self.clone();
+			crate::microtask::queue(move || {
+				this.batch_run();
+				this.with_thread(|batch| batch.microtask = false);
+			});
+		}
+	}
+
+	/// Queues a reaction to run when the batch of the current thread ends.
+	/// Outside of a batch of this runtime, it runs once the batch of another
+	/// runtime that invalidated it ends.
+	pub(crate) fn enqueue(&self, order: Order, reactive: Weak<dyn Reactive + Send + Sync>) {
+		let started = self.with_thread(|batch| {
+			batch.enqueue(order, reactive);
+			batch.started
+		});
+
+		if !started {
+			DEFERRED.with(|deferred| {
+				let mut deferred = deferred.borrow_mut();
+				if !deferred
+					.iter()
+					.any(|runtime| runtime.body.id == self.body.id)
+				{
+					deferred.push(self.clone());
+				}
+			});
+		}
+	}
+
+	/// Ends the outermost batch of this runtime on this thread, running its
+	/// reactions and the ones it queued in other runtimes.
+	fn batch_end(&self) {
+		self.batch_stop();
+		if !self.is_microtask_scheduled() {
+			self.batch_run();
+		}
+
+		if Runtime::thread_in_batch() {
+			return;
+		}
+
+		while let Some(runtime) = DEFERRED.with(|deferred| deferred.borrow_mut().pop()) {
+			// a batch of its own runs them when it ends
+			if !runtime.in_batch() {
+				runtime.batch_run();
+			}
+		}
+	}
+
+	/// Runs the queued reactions in rounds, each round in [`Order`],
+	/// until no new ones get queued. The batch stays open meanwhile, so
+	/// reactions queued by a round run in the next one.
+	pub(crate) fn batch_run(&self) {
+		let _stop = self.batch_start().then(|| Stop(self));
+		let mut rounds = Rounds::new(config::max_rounds());
+		let executor = self.body.executor.lock().clone();
+
+		loop {
+			let changed = self.with_thread(|batch| std::mem::take(&mut batch.changed));
+
+			if changed.is_empty() {
+				break;
+			}
+
+			let Some(traced) = rounds.start() else {
+				// the reactions left behind run again on the next change
+				for queued in changed.into_values() {
+					if let Some(reactive) = queued.reactive.upgrade() {
+						reactive.dequeue();
+					}
+				}
+				rounds.diverged();
+			};
+
+			let reactions: Vec<_> = changed
+				.into_iter()
+				.filter_map(|(order, queued)| Some((order, queued.reactive.upgrade()?)))
+				.collect();
+
+			let names: Vec<_> = match traced {
+				true => reactions
+					.iter()
+					.map(|(_, reactive)| reactive.name())
+					.collect(),
+				false => Vec::new(),
+			};
+
+			match &executor {
+				Some(executor) => self.run_parallel(executor.as_ref(), reactions, traced),
+				None => reactions
+					.into_iter()
+					.for_each(|(_, reactive)| reactive.update()),
+			}
+
+			if traced {
+				rounds.finish(names);
+			}
+		}
+	}
+
+	/// Runs each priority of a round with `executor`, and queues the
+	/// reactions they invalidated on this thread, in this runtime or another
+	/// one. The `Var`s they changed are noted on this thread too when the
+	/// round is `traced`.
+	fn run_parallel(
+		&self,
+		executor: &dyn Executor,
+		reactions: Vec<(Order, Arc<dyn Reactive + Send + Sync>)>,
+		traced: bool,
+	) {
+		let queued = Arc::new(Mutex::new(Vec::new()));
+
+		for group in reactions.chunk_by(|a, b| a.0.priority() == b.0.priority()) {
+			if let [(_, reactive)] = group {
+				reactive.update();
+				continue;
+			}
+
+			let tasks = group
+				.iter()
+				.map(|(_, reactive)| {
+					let (runtime, reactive, queued) =
+						(self.clone(), reactive.clone(), queued.clone());
+					Box::new(move || {
+						let run =
+							|| executor::as_worker(|| runtime.run_detached(reactive.as_ref()));
+						let done = match traced {
+							true => rounds::recorded(run),
+							false => (run(), Vec::new()),
+						};
+						queued.lock().push(done);
+					}) as Task
+				})
+				.collect();
+
+			executor.run(tasks);
+		}
+
+		let queued = std::mem::take(&mut *queued.lock());
+		for (detached, writes) in queued {
+			rounds::merge(writes);
+			for (runtime, changed) in detached {
+				// the ones of other runtimes run once this batch ends
+				for (order, item) in changed {
+					runtime.enqueue(order, item.reactive);
+				}
+			}
+		}
+	}
+
+	/// Runs `reactive` in this runtime and a batch of the current thread,
+	/// returning the reactions it queued, by runtime, instead of running them.
+	fn run_detached(&self, reactive: &dyn Reactive) -> Vec<(Runtime, BTreeMap<Order, Queued>)> {
+		let changed = self.enter(|| {
+			let _stop = self.batch_start().then(|| Stop(self));
+			reactive.update();
+			self.with_thread(|batch| std::mem::take(&mut batch.changed))
+		});
+
+		let deferred = DEFERRED.with(|deferred| std::mem::take(&mut *deferred.borrow_mut()));
+		let mut detached = vec![(self.clone(), changed)];
+		for runtime in deferred {
+			let changed = runtime.with_thread(|batch| std::mem::take(&mut batch.changed));
+			detached.push((runtime, changed));
+		}
+
+		detached
+	}
+
+	fn batch_start(&self) -> bool {
+		self.with_thread(|batch| !std::mem::replace(&mut batch.started, true))
+	}
+
+	fn batch_stop(&self) {
+		self.with_thread(|batch| batch.started = false);
+	}
+
+	fn is_microtask_scheduled(&self) -> bool {
+		self.with_thread(|batch| batch.microtask)
+	}
+
+	fn with_thread<R>(&self, func: impl FnOnce(&mut ThreadBatch) -> R) -> R {
+		let id = self.body.id;
+		BATCHES.with(|batches| {
+			let mut batches = batches.borrow_mut();
+			let batch = batches.entry(id).or_default();
+			let result = func(batch);
+
+			// runtimes come and go, only the ones inside a batch are kept
+			if batch.is_idle() {
+				batches.remove(&id);
+			}
+
+			result
+		})
+	}
+}
+
+/// Ends the batch it started, even on panic.
+struct Stop<'a>(&'a Runtime);
+
+impl Drop for Stop<'_> {
+	fn drop(&mut self) {
+		self.0.batch_stop();
+	}
+}
+
+impl std::fmt::Debug for Runtime {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Runtime")
+			.field("addr", &Arc::as_ptr(&self.body))
+			.finish()
+	}
+}
@@ -0,0 +1,305 @@
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use futures::FutureExt;
+use parking_lot::Mutex;
+
+use crate::pace::Pacer;
+use crate::spawner::Spawner;
+
+/// Deferred run of a reaction. Running it once the reaction is
+/// up to date again, or was dropped, does nothing.
+pub type Task = Box<dyn FnOnce() + Send>;
+
+/// Decides when a [`Reaction`](crate::arc::Reaction) runs after a batch
+/// invalidated it. Without one, it runs when the outermost batch ends.
+///
+/// Every batch that invalidates the reaction schedules a new task, so a
+/// scheduler can coalesce them. Any `Fn(Task)` is a scheduler too, and
+/// [`Pinned`] sends the tasks to the thread of a specific executor.
+pub trait Scheduler: Send + Sync + 'static {
+	fn schedule(&self, task: Task);
+}
+
+impl<F> Scheduler for F
+where
+	F: Fn(Task) + Send + Sync + 'static,
+{
+	fn schedule(&self, task: Task) {
+		self(task)
+	}
+}
+
+/// Runs the reaction in a task of its own.
+#[derive(Default)]
+pub struct Spawned {
+	spawner: Option<Arc<dyn Spawner>>,
+}
+
+impl Spawned {
+	pub fn new() -> Self {
+		Spawned::default()
+	}
+
+	/// Runs the tasks with `spawner` instead of the [default](crate::spawner::default) one.
+	pub fn with_spawner(spawner: impl Spawner + 'static) -> Self {
+		Spawned {
+			spawner: Some(Arc::new(spawner)),
+		}
+	}
+}
+
+impl Scheduler for Spawned {
+	fn schedule(&self, task: Task) {
+		let spawner = self.spawner.clone().unwrap_or_else(crate::spawner::default);
+		spawner.spawn(async move { task() }.boxed());
+	}
+}
+
+/// Collects the tasks of its reactions until the next [`Frame::tick`],
+/// for example to run them once per rendered frame.
+#[derive(Clone, Default)]
+pub struct Frame {
+	tasks: Arc<Mutex<Vec<Task>>>,
+}
+
+impl Frame {
+	pub fn new() -> Self {
+		Frame::default()
+	}
+
+	/// Runs the reactions invalidated since the previous tick.
+	pub fn tick(&self) {
+		let tasks = std::mem::take(&mut *self.tasks.lock());
+		for task in tasks {
+			task();
+		}
+	}
+}
+
+impl Scheduler for Frame {
+	fn schedule(&self, task: Task) {
+		self.tasks.lock().push(task);
+	}
+}
+
+/// Sends the tasks of its reactions over a channel, so that they run on the
+/// thread that receives them, such as the one of a specific executor.
+#[derive(Clone)]
+pub struct Pinned {
+	sender: mpsc::Sender<Task>,
+}
+
+impl Pinned {
+	/// Sends the tasks to `sender`. Its receiver runs them.
+	pub fn new(sender: mpsc::Sender<Task>) -> Self {
+		Pinned { sender }
+	}
+
+	/// Runs the tasks on a thread of their own, which
+	/// exits once every clone of this scheduler is dropped.
+	pub fn thread() -> Self {
+		let (sender, receiver) = mpsc::channel::<Task>();
+		std::thread::spawn(move || {
+			for task in receiver {
+				task();
+			}
+		});
+		Pinned { sender }
+	}
+}
+
+impl Scheduler for Pinned {
+	fn schedule(&self, task: Task) {
+		// nothing runs the reactions of a closed channel anymore
+		let _ = self.sender.send(task);
+	}
+}
+
+/// Debounces or throttles one reaction with tokio time.
+/// Only the latest task of a window runs, so it is not meant
+/// to be shared between reactions.
+pub struct Timed {
+	spawner: Option<Arc<dyn Spawner>>,
+	state: Arc<Mutex<TimedState>>,
+}
+
+#[derive(Default)]
+struct TimedState {
+	pacer: Pacer,
+	generation: u64,
+}
+
+impl Timed {
+	/// Runs the reaction once its inputs stayed the same for `duration`.
+	pub fn debounce(duration: Duration) -> Self {
+		let mut state = TimedState::default();
+		state.pacer.debounce(duration);
+		Timed::new(state)
+	}
+
+	/// Runs the reaction at most once per `duration`.
+	pub fn throttle(duration: Duration) -> Self {
+		let mut state = TimedState::default();
+		state.pacer.throttle(duration);
+		Timed::new(state)
+	}
+
+	/// Waits with `spawner` instead of the [default](crate::spawner::default) one.
+	pub fn with_spawner(mut self, spawner: impl Spawner + 'static) -> Self {
+		self.spawner = Some(Arc::new(spawner));
+		self
+	}
+
+	fn new(state: TimedState) -> Self {
+		Timed {
+			spawner: None,
+			state: Arc::new(Mutex::new(state)),
+		}
+	}
+}
+
+impl Scheduler for Timed {
+	fn schedule(&self, task: Task) {
+		let (start, generation) = {
+			let mut state = self.state.lock();
+			state.generation += 1;
+			(state.pacer.schedule(), state.generation)
+		};
+
+		let state = self.state.clone();
+		let run = async move {
+			if let Some(start) = start {
+				tokio::time::sleep_until(start).await;
+			}
+
+			// a later task took over
+			if state.lock().generation == generation {
+				task();
+			}
+		};
+
+		let spawner = self.spawner.clone().unwrap_or_else(crate::spawner::default);
+		spawner.spawn(run.boxed());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::arc::{batch, Reaction, Var};
+
+	fn reaction(value: &Var<i32>, runs: &Arc<Mutex<Vec<i32>>>) -> Reaction {
+		let (value, runs) = (value.clone(), runs.clone());
+		Reaction::new(Box::new(move |cx| runs.lock().push(value.get(cx))))
+	}
+
+	#[test]
+	fn coalesces_per_frame() {
+		let value = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+		let frame = Frame::new();
+
+		let reaction = reaction(&value, &runs).with_scheduler(frame.clone());
+		reaction.update();
+
+		batch(|| value.set(2));
+		batch(|| value.set(3));
+		assert_eq!(*runs.lock(), vec![1]);
+
+		frame.tick();
+		frame.tick();
+		assert_eq!(*runs.lock(), vec![1, 3]);
+	}
+
+	#[test]
+	fn schedules_once_per_batch() {
+		let (a, b) = (Var::new(1), Var::new(1));
+		let tasks = Arc::new(Mutex::new(Vec::new()));
+
+		let reaction = Reaction::new(Box::new({
+			let (a, b) = (a.clone(), b.clone());
+			move |cx| {
+				a.get(cx);
+				b.get(cx);
+			}
+		}))
+		.with_scheduler({
+			let tasks = tasks.clone();
+			move |task| tasks.lock().push(task)
+		});
+		reaction.update();
+
+		batch(|| {
+			a.set(2);
+			b.set(2);
+		});
+		assert_eq!(tasks.lock().len(), 1);
+
+		// already waiting for its task, so a write outside of a batch is fine
+		a.set(3);
+		assert_eq!(tasks.lock().len(), 1);
+
+		batch(|| b.set(3));
+		assert_eq!(tasks.lock().len(), 2);
+	}
+
+	#[test]
+	fn runs_on_a_pinned_thread() {
+		let value = Var::new(1);
+		let (sender, receiver) = mpsc::channel();
+
+		let reaction = Reaction::new(Box::new({
+			let value = value.clone();
+			move |cx| {
+				let run = (value.get(cx), std::thread::current().id());
+				sender.send(run).unwrap();
+			}
+		}))
+		.with_scheduler(Pinned::thread());
+		reaction.update();
+		assert_eq!(receiver.recv().unwrap(), (1, std::thread::current().id()));
+
+		let timeout = Duration::from_secs(5);
+		batch(|| value.set(2));
+		let (second, pinned) = receiver.recv_timeout(timeout).unwrap();
+		batch(|| value.set(3));
+		assert_eq!(receiver.recv_timeout(timeout).unwrap(), (3, pinned));
+		assert_eq!(second, 2);
+		assert_ne!(pinned, std::thread::current().id());
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn debounces_runs() {
+		let value = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let reaction =
+			reaction(&value, &runs).with_scheduler(Timed::debounce(Duration::from_millis(100)));
+		reaction.update();
+
+		batch(|| value.set(2));
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		batch(|| value.set(3));
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(*runs.lock(), vec![1]);
+
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(*runs.lock(), vec![1, 3]);
+	}
+
+	#[tokio::test]
+	async fn runs_in_a_task() {
+		let value = Var::new(1);
+		let runs = Arc::new(Mutex::new(Vec::new()));
+
+		let reaction = reaction(&value, &runs).with_scheduler(Spawned::new());
+		reaction.update();
+
+		batch(|| value.set(2));
+		assert_eq!(*runs.lock(), vec![1]);
+
+		tokio::task::yield_now().await;
+		assert_eq!(*runs.lock(), vec![1, 2]);
+	}
+}
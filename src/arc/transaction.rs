@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+/// Write that can be undone when its transaction aborts.
+pub(crate) trait Undo {
+	fn undo(self: Box<Self>);
+}
+
+/// Originals of the `Var`s written by one transaction.
+#[derive(Default)]
+struct Frame {
+	written: HashSet<usize>,
+	undo: Vec<(usize, Box<dyn Undo>)>,
+}
+
+thread_local! {
+	static JOURNAL: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Whether a transaction is open on this thread.
+pub(crate) fn is_recording() -> bool {
+	JOURNAL.with(|journal| !journal.borrow().is_empty())
+}
+
+/// Records the original value of the node at `addr`, unless the innermost
+/// transaction already wrote it. `original` is only called when recorded.
+pub(crate) fn record(addr: usize, original: impl FnOnce() -> Box<dyn Undo>) {
+	JOURNAL.with(|journal| {
+		let mut journal = journal.borrow_mut();
+		if let Some(frame) = journal.last_mut() {
+			if frame.written.insert(addr) {
+				frame.undo.push((addr, original()));
+			}
+		}
+	})
+}
+
+pub(crate) fn begin() {
+	JOURNAL.with(|journal| journal.borrow_mut().push(Frame::default()));
+}
+
+/// Closes the innermost transaction. A committed one hands its originals
+/// over to the enclosing transaction, an aborted one restores them.
+pub(crate) fn end(commit: bool) {
+	let frame = JOURNAL.with(|journal| journal.borrow_mut().pop().unwrap_or_default());
+
+	if commit {
+		JOURNAL.with(|journal| {
+			if let Some(parent) = journal.borrow_mut().last_mut() {
+				for (addr, undo) in frame.undo {
+					// the enclosing transaction keeps the older original
+					if parent.written.insert(addr) {
+						parent.undo.push((addr, undo));
+					}
+				}
+			}
+		});
+	} else {
+		for (_, undo) in frame.undo.into_iter().rev() {
+			undo.undo();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::panic::AssertUnwindSafe;
+	use std::sync::Arc;
+
+	use parking_lot::Mutex;
+
+	use crate::arc::{transaction, Computed, Reaction, Var};
+
+	fn reaction(value: &Computed<i32>, runs: &Arc<Mutex<Vec<i32>>>) -> Reaction {
+		let reaction = Reaction::new(Box::new({
+			let (value, runs) = (value.clone(), runs.clone());
+			move |cx| runs.lock().push(*value.get(cx))
+		}));
+		reaction.update();
+		reaction
+	}
+
+	#[test]
+	fn rolls_back_on_error() {
+		let a = Var::new(1);
+		let b = Var::new(10);
+		let sum = Computed::new(Box::new({
+			let (a, b) = (a.clone(), b.clone());
+			move |cx| a.get(cx) + b.get(cx)
+		}));
+
+		let runs = Arc::new(Mutex::new(Vec::new()));
+		let _reaction = reaction(&sum, &runs);
+
+		let result = transaction(|| {
+			a.set(2);
+			b.update(|b| *b += 10);
+			a.set(3);
+			assert_eq!(*sum.get_once(), 23);
+			Err::<(), _>("invalid")
+		});
+
+		assert_eq!(result, Err("invalid"));
+		assert_eq!((a.get_once(), b.get_once()), (1, 10));
+		assert_eq!(*runs.lock(), vec![11]);
+
+		transaction(|| {
+			a.set(2);
+			Ok::<_, ()>(())
+		})
+		.unwrap();
+		assert_eq!(*runs.lock(), vec![11, 12]);
+	}
+
+	#[test]
+	fn rolls_back_on_panic() {
+		let a = Var::new(1);
+		let doubled = a.map(|a| a * 2);
+
+		let runs = Arc::new(Mutex::new(Vec::new()));
+		let _reaction = reaction(&doubled, &runs);
+
+		let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+			let _ = transaction(|| {
+				a.set(2);
+				panic!("aborted");
+				#[allow(unreachable_code)]
+				Ok::<_, ()>(())
+			});
+		}));
+
+		assert!(result.is_err());
+		assert_eq!(a.get_once(), 1);
+		assert_eq!(*runs.lock(), vec![2]);
+
+		crate::arc::batch(|| a.set(3));
+		assert_eq!(*runs.lock(), vec![2, 6]);
+	}
+
+	#[test]
+	fn nests_transactions() {
+		let a = Var::new(1);
+		let b = Var::new(1);
+
+		let result = transaction(|| {
+			a.set(2);
+			transaction(|| {
+				a.set(3);
+				b.set(3);
+				Ok::<_, ()>(())
+			})?;
+
+			let inner = transaction(|| {
+				b.set(4);
+				Err::<(), _>(())
+			});
+			assert_eq!((a.get_once(), b.get_once()), (3, 3));
+			inner
+		});
+
+		assert_eq!(result, Err(()));
+		assert_eq!((a.get_once(), b.get_once()), (1, 1));
+	}
+
+	#[test]
+	fn revalidates_aborted_reactions_when_the_root_ends() {
+		let a = Var::new(1);
+		let doubled = a.map(|a| a * 2);
+
+		let runs = Arc::new(Mutex::new(Vec::new()));
+		let _reaction = reaction(&doubled, &runs);
+
+		transaction(|| {
+			let _ = transaction(|| {
+				a.set(2);
+				Err::<(), _>(())
+			});
+			assert_eq!(*runs.lock(), vec![2]);
+			Ok::<_, ()>(())
+		})
+		.unwrap();
+		assert_eq!(*runs.lock(), vec![2]);
+
+		transaction(|| {
+			let _ = transaction(|| {
+				a.set(2);
+				Err::<(), _>(())
+			});
+			a.set(3);
+			assert_eq!(*runs.lock(), vec![2]);
+			Ok::<_, ()>(())
+		})
+		.unwrap();
+		assert_eq!(*runs.lock(), vec![2, 6]);
+	}
+}
@@ -36,9 +36,11 @@ where
 	}
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum Ref<'a, T> {
 	Ref(&'a T),
 	Guard(MappedRwLockReadGuard<'a, T>),
+	Shared(Arc<T>),
 }
 
 impl<'a, T> Deref for Ref<'a, T> {
@@ -48,6 +50,7 @@ impl<'a, T> Deref for Ref<'a, T> {
 		match self {
 			Ref::Guard(guard) => guard.deref(),
 			Ref::Ref(t) => t,
+			Ref::Shared(t) => t,
 		}
 	}
 }
@@ -4,16 +4,18 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::sync::{Arc, Weak};
 
-use parking_lot::{MappedMutexGuard, MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
+use arc_swap::ArcSwapOption;
+use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::arc::addr::WeakAddr;
 use crate::arc::evaluation::Evaluation;
+use crate::arc::transaction::{self, Undo};
 use crate::arc::value::{Access, Value};
-use crate::arc::{Computed, Derived, Invalid, Observable, Version};
+use crate::arc::{batch, Computed, Derived, Invalid, Observable, Runtime, Version};
+use crate::config;
+use crate::derivation;
 use crate::hashed::Hashed;
 
-type Ref<'a, T> = MappedMutexGuard<'a, T>;
-
 pub struct Var<T> {
 	body: Arc<VarBody<T>>,
 }
@@ -32,7 +34,13 @@ impl<T: 'static + Send + Sync> TryFrom<Arc<dyn Any + Send + Sync>> for Var<T> {
 }
 
 pub struct VarBody<T> {
-	value: RwLock<Hashed<T>>,
+	name: &'static str,
+	/// Locked by the guards of [`Var::get_ref`] and by writers. Writers
+	/// change the value in place only while nothing else shares the `Arc`.
+	value: RwLock<Hashed<Arc<T>>>,
+	/// The current `Arc`, shared by the first [`Var::get_arc`] after
+	/// a write and loaded without locking until the next one.
+	snapshot: ArcSwapOption<Hashed<Arc<T>>>,
 	inner: Mutex<VarInner<T>>,
 }
 
@@ -73,12 +81,22 @@ where
 	T: Send + Sync + 'static,
 {
 	pub fn new(value: T) -> Self
+	where
+		T: Hash,
+	{
+		Self::new_with_name("<unnamed>", value)
+	}
+
+	/// Creates a `Var` whose name shows up when reactions do not settle.
+	pub fn new_with_name(name: &'static str, value: T) -> Self
 	where
 		T: Hash,
 	{
 		Var {
 			body: Arc::new_cyclic(|this| VarBody {
-				value: RwLock::new(Hashed::new(value)),
+				name,
+				snapshot: ArcSwapOption::empty(),
+				value: RwLock::new(Hashed::new(Arc::new(value))),
 				inner: Mutex::new(VarInner {
 					used_by: BTreeSet::new(),
 					this: this.clone(),
@@ -117,6 +135,21 @@ where
 		self.body.get_once()
 	}
 
+	/// Shares the current value. Unlike the guard of [`Var::get_ref`],
+	/// it can be kept across writes, which then store a new value instead of
+	/// changing this one. The first call after a write takes the read lock,
+	/// so it waits for writers like [`Var::get_ref`] does; the next ones
+	/// share the same `Arc` without locking.
+	#[inline]
+	pub fn get_arc(&self, eval: &impl AsRef<Evaluation>) -> Arc<T> {
+		self.body.get_arc(eval.as_ref())
+	}
+
+	#[inline]
+	pub fn get_arc_once(&self) -> Arc<T> {
+		self.body.snapshot().value.clone()
+	}
+
 	#[inline]
 	pub fn get_once(&self) -> T
 	where
@@ -130,37 +163,88 @@ where
 	where
 		T: Hash,
 	{
-		self.body.set(value)
+		config::write(
+			self.body.name,
+			derivation::addr(Arc::as_ptr(&self.body)),
+			Runtime::thread_in_batch,
+			|write| batch(write),
+			|| self.body.set(value),
+		)
 	}
 
 	#[inline]
 	pub fn toggle(&self)
 	where
-		T: Toggle + Hash,
+		T: Toggle + Hash + Clone,
 	{
 		self.update(T::toggle)
 	}
 
+	/// Sets the value and returns the previous one, copied if a
+	/// [`Var::get_arc`] snapshot or the open [`transaction`](crate::arc::transaction)
+	/// still shares it.
 	#[inline]
 	pub fn replace(&self, value: T) -> T
+	where
+		T: Hash + Clone,
+	{
+		config::write(
+			self.body.name,
+			derivation::addr(Arc::as_ptr(&self.body)),
+			Runtime::thread_in_batch,
+			|write| batch(write),
+			|| self.body.replace(value),
+		)
+	}
+
+	/// Changes the value in place, or a copy of it if a [`Var::get_arc`]
+	/// snapshot or the open [`transaction`](crate::arc::transaction) still
+	/// shares the current one.
+	#[inline]
+	pub fn update(&self, func: impl FnOnce(&mut T))
+	where
+		T: Hash + Clone,
+	{
+		config::write(
+			self.body.name,
+			derivation::addr(Arc::as_ptr(&self.body)),
+			Runtime::thread_in_batch,
+			|write| batch(write),
+			|| self.body.update(func),
+		)
+	}
+
+	/// Whether a derivation read this `Var` and still depends on it.
+	pub(crate) fn is_observed(&self) -> bool {
+		!self.body.inner.lock().used_by.is_empty()
+	}
+
+	/// Sets the value to `func` of the current one, under the write lock, and
+	/// returns the previous value with the hash of the new one. The write is
+	/// not recorded in the open transaction.
+	pub(crate) fn swap_with(&self, func: impl FnOnce(&T) -> T) -> (Arc<T>, u64)
 	where
 		T: Hash,
 	{
-		self.body.replace(value)
+		let current = self.body.value.write();
+		let new = Hashed::new(Arc::new(func(&current.value)));
+		let hash = new.hash;
+		let old = self.body.swap_in(current, new);
+		(old.value, hash)
 	}
 
-	#[inline]
-	pub fn update(&self, func: impl FnOnce(&mut T))
+	/// Replaces the value only if its hash is still `hash`.
+	pub(crate) fn replace_if(&self, hash: u64, value: Arc<T>) -> bool
 	where
 		T: Hash,
 	{
-		self.body.update(func)
+		self.body.replace_if(hash, value)
 	}
 }
 
 impl<T> VarBody<T> {
 	pub fn get_once(&self) -> MappedRwLockReadGuard<'_, T> {
-		RwLockReadGuard::map(self.value.read(), |s| &s.value)
+		RwLockReadGuard::map(self.value.read(), |s| &*s.value)
 	}
 
 	pub fn get<'a>(&'a self, eval: &'_ Evaluation) -> MappedRwLockReadGuard<'a, T>
@@ -175,56 +259,155 @@ impl<T> VarBody<T> {
 			self_mut.used_by(eval.parent());
 		}
 
-		RwLockReadGuard::map(value, |v| &v.value)
+		RwLockReadGuard::map(value, |v| &*v.value)
 	}
 
-	pub fn update<'a>(&'a self, func: impl FnOnce(&mut T))
+	pub fn get_arc(&self, eval: &Evaluation) -> Arc<T>
 	where
-		T: 'static + Hash,
+		T: Send + Sync + 'static,
 	{
-		let mut value = self.value.write();
-		func(&mut value.value);
-		let hash = fxhash::hash64(&value.value);
-		if value.hash != hash {
-			value.hash = hash;
+		// registered first, so that a write after the snapshot is taken
+		// invalidates the caller instead of being missed
+		let this = {
+			let mut self_mut = self.inner.lock();
+			self_mut.used_by(eval.parent());
+			self_mut.this.upgrade().unwrap()
+		};
+
+		let value = self.snapshot();
+		eval.based_on(this, Version::Hash(value.hash));
+		value.value.clone()
+	}
+
+	/// The current value, shared again if a write dropped it.
+	fn snapshot(&self) -> Arc<Hashed<Arc<T>>> {
+		if let Some(snapshot) = self.snapshot.load_full() {
+			return snapshot;
+		}
+
+		// writers drop the snapshot under the write lock,
+		// so the one stored here is of the current value
+		let value = self.value.read();
+		let snapshot = Arc::new(Hashed {
+			value: value.value.clone(),
+			hash: value.hash,
+		});
+		self.snapshot.store(Some(snapshot.clone()));
+		snapshot
+	}
+
+	pub fn update(&self, func: impl FnOnce(&mut T))
+	where
+		T: Send + Sync + 'static + Hash + Clone,
+	{
+		let mut current = self.write();
+		func(Arc::make_mut(&mut current.value));
+
+		let hash = current.hash;
+		current.hash = fxhash::hash64(&*current.value);
+		if hash != current.hash {
+			std::mem::drop(current);
 			self.invalidate()
 		}
 	}
 
 	pub fn replace(&self, value: T) -> T
 	where
-		T: Hash,
+		T: Send + Sync + 'static + Hash + Clone,
 	{
-		let mut current = self.value.write();
-		let new = Hashed::new(value);
-		let hash = new.hash;
+		let old = self.swap_in(self.write(), Hashed::new(Arc::new(value)));
+		Arc::try_unwrap(old.value).unwrap_or_else(|old| T::clone(&old))
+	}
+
+	pub fn set(&self, value: T)
+	where
+		T: Send + Sync + 'static + Hash,
+	{
+		self.swap_in(self.write(), Hashed::new(Arc::new(value)));
+	}
+
+	/// Locks the value for a write. The snapshot is dropped and, in a
+	/// transaction, the current `Arc` is kept as the original under the
+	/// same lock, so no other write can come in between.
+	fn write(&self) -> RwLockWriteGuard<'_, Hashed<Arc<T>>>
+	where
+		T: Send + Sync + 'static,
+	{
+		let current = self.value.write();
+		self.snapshot.store(None);
+
+		if transaction::is_recording() {
+			let this = self.inner.lock().this.upgrade().unwrap();
+			let addr = Arc::as_ptr(&this) as usize;
+			let value = Hashed {
+				value: current.value.clone(),
+				hash: current.hash,
+			};
+			transaction::record(addr, move || Box::new(Original { var: this, value }));
+		}
+
+		current
+	}
 
+	/// Stores `new` under the `current` lock and invalidates
+	/// the observers if its hash differs.
+	fn swap_in(
+		&self,
+		mut current: RwLockWriteGuard<'_, Hashed<Arc<T>>>,
+		new: Hashed<Arc<T>>,
+	) -> Hashed<Arc<T>> {
 		let old = std::mem::replace(&mut *current, new);
-		if old.hash != hash {
-			std::mem::drop(current);
+		self.snapshot.store(None);
+		let changed = old.hash != current.hash;
+		std::mem::drop(current);
+
+		if changed {
 			self.invalidate();
 		}
 
-		return old.value;
+		old
 	}
 
-	pub fn set(&self, value: T)
+	pub(crate) fn replace_if(&self, hash: u64, value: Arc<T>) -> bool
 	where
 		T: Hash,
 	{
-		let _ = self.replace(value);
+		let mut current = self.value.write();
+		if current.hash != hash {
+			return false;
+		}
+
+		let new = Hashed::new(value);
+		let changed = new.hash != hash;
+		*current = new;
+		self.snapshot.store(None);
+		std::mem::drop(current);
+
+		if changed {
+			self.invalidate();
+		}
+
+		true
 	}
 
 	fn invalidate(&self) {
-		let mut self_mut = self.inner.lock();
-		self_mut.used_by.retain(|item| {
-			if let Some(item) = item.upgrade() {
-				item.invalidate(Invalid::Definitely);
-				true
-			} else {
-				false
-			}
-		});
+		crate::rounds::record_write(self.name);
+
+		let mut observers = Vec::new();
+		self.inner
+			.lock()
+			.used_by
+			.retain(|item| match item.upgrade() {
+				Some(item) => {
+					observers.push(item);
+					true
+				}
+				None => false,
+			});
+
+		for observer in observers {
+			observer.invalidate(Invalid::Definitely);
+		}
 	}
 
 	fn used_by(&self, derived: Weak<dyn Derived>) {
@@ -236,6 +419,18 @@ impl<T> VarBody<T> {
 	}
 }
 
+/// Value of a `Var` from before a transaction.
+struct Original<T> {
+	var: Arc<VarBody<T>>,
+	value: Hashed<Arc<T>>,
+}
+
+impl<T> Undo for Original<T> {
+	fn undo(self: Box<Self>) {
+		self.var.swap_in(self.var.value.write(), self.value);
+	}
+}
+
 impl<T> VarInner<T> {
 	pub fn used_by(&mut self, derived: Weak<dyn Derived + Send + Sync>) {
 		self.used_by.insert(WeakAddr::new(derived));
@@ -256,11 +451,11 @@ impl<T: Send + Sync + 'static> Observable for VarBody<T> {
 	}
 
 	fn used_by(&self, derived: Weak<dyn Derived>) {
-		VarBody::used_by(&self, derived)
+		VarBody::used_by(self, derived)
 	}
 
 	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		VarBody::not_used_by(&self, derived)
+		VarBody::not_used_by(self, derived)
 	}
 }
 
@@ -273,7 +468,7 @@ where
 	}
 
 	fn get_once(&self) -> crate::arc::value::Ref<'_, T> {
-		crate::arc::value::Ref::Guard(VarBody::get_once(&self))
+		crate::arc::value::Ref::Guard(VarBody::get_once(self))
 	}
 }
 
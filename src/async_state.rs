@@ -0,0 +1,178 @@
+use std::convert::Infallible;
+use std::hash::Hash;
+
+use tokio::time::Instant;
+
+/// Loading state of an async derived value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AsyncState<T, E = Infallible> {
+	/// Nothing has been loaded yet.
+	#[default]
+	Pending,
+	/// The latest run finished with a value.
+	Ready(T),
+	/// A new run is in flight, the value is from the previous one.
+	Refreshing(T),
+	/// The latest run failed, the last good value is kept if there was one.
+	Failed(E, Option<T>),
+	/// The latest run failed and another one is scheduled by the retry policy.
+	Retrying(E, Option<T>, Attempt),
+}
+
+/// Marker error of a derivation that read an async value which has not
+/// loaded yet. Computations returning `Result<_, Pending>` can short-circuit
+/// with `?` and become pending as a whole, their dependencies stay recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Pending;
+
+impl std::fmt::Display for Pending {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("async value is pending")
+	}
+}
+
+impl std::error::Error for Pending {}
+
+/// Why a derivation could not read an async value: it has not loaded yet,
+/// or its first run failed and there is no earlier value to fall back on.
+/// Converts from [`Pending`], and into it for values that cannot fail
+/// or only fail with [`Pending`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NotReady<E> {
+	Pending,
+	Failed(E),
+}
+
+impl<E> From<Pending> for NotReady<E> {
+	fn from(_: Pending) -> Self {
+		NotReady::Pending
+	}
+}
+
+impl From<NotReady<Infallible>> for Pending {
+	fn from(_: NotReady<Infallible>) -> Self {
+		Pending
+	}
+}
+
+/// A value that failed because its own inputs were pending is pending.
+impl From<NotReady<Pending>> for Pending {
+	fn from(_: NotReady<Pending>) -> Self {
+		Pending
+	}
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for NotReady<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			NotReady::Pending => Pending.fmt(f),
+			NotReady::Failed(error) => error.fmt(f),
+		}
+	}
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for NotReady<E> {}
+
+/// A scheduled retry of an async derived value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attempt {
+	/// Number of the scheduled run, the first retry is run number 2.
+	pub number: u32,
+	/// When the run starts.
+	pub at: Instant,
+}
+
+impl<T, E> AsyncState<T, E> {
+	/// The latest known value, fresh or stale.
+	pub fn value(&self) -> Option<&T> {
+		match self {
+			AsyncState::Pending => None,
+			AsyncState::Ready(value) | AsyncState::Refreshing(value) => Some(value),
+			AsyncState::Failed(_, value) | AsyncState::Retrying(_, value, _) => value.as_ref(),
+		}
+	}
+
+	pub fn error(&self) -> Option<&E> {
+		match self {
+			AsyncState::Failed(error, _) | AsyncState::Retrying(error, _, _) => Some(error),
+			_ => None,
+		}
+	}
+
+	/// The scheduled retry, if the latest run failed and will be retried.
+	pub fn attempt(&self) -> Option<&Attempt> {
+		match self {
+			AsyncState::Retrying(_, _, attempt) => Some(attempt),
+			_ => None,
+		}
+	}
+
+	/// The latest known value, or why there is none.
+	pub fn read(&self) -> Result<&T, NotReady<E>>
+	where
+		E: Clone,
+	{
+		match self {
+			AsyncState::Failed(error, None) => Err(NotReady::Failed(error.clone())),
+			state => state.value().ok_or(NotReady::Pending),
+		}
+	}
+
+	pub fn is_ready(&self) -> bool {
+		matches!(self, AsyncState::Ready(_))
+	}
+
+	/// Whether a run is expected to produce a new value.
+	pub fn is_loading(&self) -> bool {
+		matches!(
+			self,
+			AsyncState::Pending | AsyncState::Refreshing(_) | AsyncState::Retrying(..)
+		)
+	}
+
+	fn into_value(self) -> Option<T> {
+		match self {
+			AsyncState::Pending => None,
+			AsyncState::Ready(value) | AsyncState::Refreshing(value) => Some(value),
+			AsyncState::Failed(_, value) | AsyncState::Retrying(_, value, _) => value,
+		}
+	}
+
+	/// State to show while a new run is in flight.
+	pub(crate) fn refreshing(self) -> Self {
+		match self.into_value() {
+			Some(value) => AsyncState::Refreshing(value),
+			None => AsyncState::Pending,
+		}
+	}
+
+	/// State after a run finished with `result`.
+	pub(crate) fn resolve(self, result: Result<T, E>) -> Self {
+		match result {
+			Ok(value) => AsyncState::Ready(value),
+			Err(error) => AsyncState::Failed(error, self.into_value()),
+		}
+	}
+
+	/// State after a run failed with `error` and another `attempt` is scheduled.
+	pub(crate) fn retrying(self, error: E, attempt: Attempt) -> Self {
+		AsyncState::Retrying(error, self.into_value(), attempt)
+	}
+
+	/// Hash used for change detection. Errors are not required
+	/// to be hashable, so every failure gets its own `failure` number.
+	pub(crate) fn fingerprint(&self, failure: u64) -> u64
+	where
+		T: Hash,
+	{
+		match self {
+			AsyncState::Pending => fxhash::hash64(&0u8),
+			AsyncState::Ready(value) => fxhash::hash64(&(1u8, value)),
+			AsyncState::Refreshing(value) => fxhash::hash64(&(2u8, value)),
+			AsyncState::Failed(_, value) => fxhash::hash64(&(3u8, failure, value)),
+			AsyncState::Retrying(_, value, attempt) => {
+				fxhash::hash64(&(4u8, failure, value, attempt.number))
+			}
+		}
+	}
+}
@@ -0,0 +1,130 @@
+//! Policies shared by the `rc` and `arc` graphs.
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::derivation::{self, Kind};
+
+/// When a `Var` may be written outside of a `batch`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnforceBatch {
+	/// A write outside of a batch runs in a batch of its own.
+	Never = 0,
+	/// A write outside of a batch panics once it invalidates a reaction.
+	#[default]
+	Observed = 1,
+	/// Every write outside of a batch panics.
+	Always = 2,
+}
+
+/// Set with [`configure`]. By default writes outside of a batch only panic
+/// once they invalidate a reaction ([`EnforceBatch::Observed`]), computeds
+/// that write are warned about, untracked reads are not, and batches run
+/// at most 100 rounds.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+	pub enforce_batch: EnforceBatch,
+	/// Panic when a `Var` is written while a `Computed` evaluates,
+	/// instead of logging a warning the first time a computed writes a `Var`.
+	pub forbid_computed_writes: bool,
+	/// Warn when a `Computed` is read outside of any computed or reaction.
+	pub warn_untracked_reads: bool,
+	/// How many rounds of reactions a batch runs before it panics,
+	/// naming the reactions and `Var`s of the last rounds. 100 by default.
+	pub max_rounds: usize,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			enforce_batch: EnforceBatch::Observed,
+			forbid_computed_writes: false,
+			warn_untracked_reads: false,
+			max_rounds: 100,
+		}
+	}
+}
+
+static ENFORCE_BATCH: AtomicU8 = AtomicU8::new(EnforceBatch::Observed as u8);
+static FORBID_COMPUTED_WRITES: AtomicBool = AtomicBool::new(false);
+static WARN_UNTRACKED_READS: AtomicBool = AtomicBool::new(false);
+static MAX_ROUNDS: AtomicUsize = AtomicUsize::new(100);
+/// Computeds and `Var`s already reported for a write, by address.
+static REPORTED: Mutex<BTreeSet<(usize, usize)>> = Mutex::new(BTreeSet::new());
+
+/// Replaces the policies of every graph of the process.
+pub fn configure(config: Config) {
+	ENFORCE_BATCH.store(config.enforce_batch as u8, Ordering::Relaxed);
+	FORBID_COMPUTED_WRITES.store(config.forbid_computed_writes, Ordering::Relaxed);
+	WARN_UNTRACKED_READS.store(config.warn_untracked_reads, Ordering::Relaxed);
+	MAX_ROUNDS.store(config.max_rounds, Ordering::Relaxed);
+}
+
+/// The policies set with [`configure`].
+pub fn config() -> Config {
+	Config {
+		enforce_batch: enforce_batch(),
+		forbid_computed_writes: FORBID_COMPUTED_WRITES.load(Ordering::Relaxed),
+		warn_untracked_reads: warn_untracked_reads(),
+		max_rounds: max_rounds(),
+	}
+}
+
+fn enforce_batch() -> EnforceBatch {
+	match ENFORCE_BATCH.load(Ordering::Relaxed) {
+		0 => EnforceBatch::Never,
+		1 => EnforceBatch::Observed,
+		_ => EnforceBatch::Always,
+	}
+}
+
+pub(crate) fn warn_untracked_reads() -> bool {
+	WARN_UNTRACKED_READS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn max_rounds() -> usize {
+	MAX_ROUNDS.load(Ordering::Relaxed)
+}
+
+/// Runs a write to the `Var` named `name` at `addr` under the configured
+/// policies. A write while a computed evaluates is logged once for each
+/// computed and `Var`, told apart by their addresses.
+/// `in_batch` tells whether the graph of the `Var` has a batch open on
+/// this thread, and `batch` opens one around a write outside of it.
+pub(crate) fn write<R>(
+	name: &'static str,
+	addr: usize,
+	in_batch: impl FnOnce() -> bool,
+	batch: impl FnOnce(&mut dyn FnMut()),
+	write: impl FnOnce() -> R,
+) -> R {
+	if let Some(computed) = derivation::current().filter(|frame| frame.kind == Kind::Computed) {
+		if FORBID_COMPUTED_WRITES.load(Ordering::Relaxed) {
+			panic!(
+				"Var `{name}` was written while Computed `{}` evaluates",
+				computed.name
+			);
+		}
+		// a computed that writes does so on every evaluation
+		if REPORTED.lock().insert((computed.addr, addr)) {
+			tracing::warn!(
+				var = name,
+				computed = computed.name,
+				"Var was written while a Computed evaluates"
+			);
+		}
+	}
+
+	match enforce_batch() {
+		EnforceBatch::Observed => write(),
+		_ if in_batch() => write(),
+		EnforceBatch::Never => {
+			let (mut write, mut result) = (Some(write), None);
+			batch(&mut || result = write.take().map(|write| write()));
+			result.unwrap()
+		}
+		EnforceBatch::Always => panic!("Var `{name}` was written outside of the `batch` function"),
+	}
+}
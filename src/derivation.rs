@@ -0,0 +1,52 @@
+use std::cell::RefCell;
+
+/// What is being evaluated on this thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+	Computed,
+	Reaction,
+}
+
+/// A derivation evaluating on this thread.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Frame {
+	pub(crate) kind: Kind,
+	pub(crate) name: &'static str,
+	/// Address of the node, to recognize it when it gets invalidated.
+	pub(crate) addr: usize,
+}
+
+thread_local! {
+	static STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `func` as the evaluation of the node at `addr`.
+pub(crate) fn run<R>(kind: Kind, name: &'static str, addr: usize, func: impl FnOnce() -> R) -> R {
+	struct Exit;
+
+	impl Drop for Exit {
+		fn drop(&mut self) {
+			STACK.with(|stack| stack.borrow_mut().pop());
+		}
+	}
+
+	STACK.with(|stack| stack.borrow_mut().push(Frame { kind, name, addr }));
+	let _exit = Exit;
+	func()
+}
+
+/// The innermost derivation evaluated on this thread.
+pub(crate) fn current() -> Option<Frame> {
+	STACK.with(|stack| stack.borrow().last().copied())
+}
+
+/// Whether the node at `addr` is evaluating on this thread, so that it
+/// is locked and cannot be invalidated right now.
+pub(crate) fn is_running(addr: usize) -> bool {
+	STACK.with(|stack| stack.borrow().iter().any(|frame| frame.addr == addr))
+}
+
+/// Address of a node, as given to [`run`].
+pub(crate) fn addr<T: ?Sized>(node: *const T) -> usize {
+	node as *const () as usize
+}
@@ -7,6 +7,18 @@ pub struct Hashed<T> {
 	pub hash: u64,
 }
 
+impl<T> Clone for Hashed<T>
+where
+	T: Clone,
+{
+	fn clone(&self) -> Self {
+		Self {
+			value: self.value.clone(),
+			hash: self.hash,
+		}
+	}
+}
+
 impl<T> PartialEq for Hashed<T> {
 	fn eq(&self, other: &Self) -> bool {
 		self.hash == other.hash
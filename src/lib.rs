@@ -1,4 +1,13 @@
 pub mod arc;
+pub mod async_state;
 pub mod capture;
+pub mod config;
+mod derivation;
 pub mod hashed;
+mod pace;
 pub mod rc;
+pub mod retry;
+mod rounds;
+pub mod spawner;
+
+pub use config::configure;
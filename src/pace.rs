@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// How soon an async value starts a run after its inputs changed.
+#[derive(Clone, Copy, Debug, Default)]
+enum Pace {
+	#[default]
+	Immediate,
+	/// Start once the inputs stayed the same for the duration.
+	Debounce(Duration),
+	/// Start at most once per duration, with the latest inputs.
+	Throttle(Duration),
+}
+
+#[derive(Default)]
+pub(crate) struct Pacer {
+	pace: Pace,
+	last_start: Option<Instant>,
+}
+
+impl Pacer {
+	pub(crate) fn debounce(&mut self, duration: Duration) {
+		self.pace = Pace::Debounce(duration);
+	}
+
+	pub(crate) fn throttle(&mut self, duration: Duration) {
+		self.pace = Pace::Throttle(duration);
+	}
+
+	/// When a run scheduled now should start, `None` to start it right away.
+	/// Uses tokio time, so a delayed run needs the tokio timer.
+	pub(crate) fn schedule(&mut self) -> Option<Instant> {
+		match self.pace {
+			Pace::Immediate => None,
+			Pace::Debounce(duration) => Some(Instant::now() + duration),
+			Pace::Throttle(duration) => {
+				let now = Instant::now();
+				let start = match self.last_start {
+					// the previous run has not started yet, the new one takes its slot
+					Some(last) if last > now => last,
+					Some(last) => (last + duration).max(now),
+					None => now,
+				};
+
+				self.last_start = Some(start);
+				(start > now).then_some(start)
+			}
+		}
+	}
+}
@@ -10,6 +10,10 @@ impl<T: ?Sized> RcAddr<T> {
 	pub fn new(ptr: Rc<T>) -> Self {
 		RcAddr { ptr }
 	}
+
+	fn addr(&self) -> *const () {
+		Rc::as_ptr(&self.ptr) as *const ()
+	}
 }
 
 impl<T: ?Sized> Deref for RcAddr<T> {
@@ -21,7 +25,7 @@ impl<T: ?Sized> Deref for RcAddr<T> {
 
 impl<T: ?Sized> PartialEq for RcAddr<T> {
 	fn eq(&self, other: &Self) -> bool {
-		Rc::as_ptr(&self.ptr).eq(&Rc::as_ptr(&other.ptr))
+		self.addr() == other.addr()
 	}
 }
 
@@ -29,13 +33,13 @@ impl<T: ?Sized> Eq for RcAddr<T> {}
 
 impl<T: ?Sized> Ord for RcAddr<T> {
 	fn cmp(&self, other: &Self) -> Ordering {
-		Rc::as_ptr(&self.ptr).cmp(&Rc::as_ptr(&other.ptr))
+		self.addr().cmp(&other.addr())
 	}
 }
 
 impl<T: ?Sized> PartialOrd for RcAddr<T> {
 	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-		Some(Rc::as_ptr(&self.ptr).cmp(&Rc::as_ptr(&other.ptr)))
+		Some(self.cmp(other))
 	}
 }
 
@@ -47,6 +51,10 @@ impl<T: ?Sized> WeakAddr<T> {
 	pub fn new(ptr: Weak<T>) -> Self {
 		WeakAddr { ptr }
 	}
+
+	fn addr(&self) -> *const () {
+		Weak::as_ptr(&self.ptr) as *const ()
+	}
 }
 
 impl<T: ?Sized> Deref for WeakAddr<T> {
@@ -58,7 +66,7 @@ impl<T: ?Sized> Deref for WeakAddr<T> {
 
 impl<T: ?Sized> PartialEq for WeakAddr<T> {
 	fn eq(&self, other: &Self) -> bool {
-		Weak::as_ptr(&self.ptr).eq(&Weak::as_ptr(&other.ptr))
+		self.addr() == other.addr()
 	}
 }
 
@@ -66,12 +74,12 @@ impl<T: ?Sized> Eq for WeakAddr<T> {}
 
 impl<T: ?Sized> Ord for WeakAddr<T> {
 	fn cmp(&self, other: &Self) -> Ordering {
-		Weak::as_ptr(&self.ptr).cmp(&Weak::as_ptr(&other.ptr))
+		self.addr().cmp(&other.addr())
 	}
 }
 
 impl<T: ?Sized> PartialOrd for WeakAddr<T> {
 	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-		Some(Weak::as_ptr(&self.ptr).cmp(&Weak::as_ptr(&other.ptr)))
+		Some(self.cmp(other))
 	}
 }
@@ -0,0 +1,732 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::hash::Hash;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+use futures::future::{AbortHandle, LocalBoxFuture};
+use futures::{Future, FutureExt};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::async_state::{AsyncState, Attempt, NotReady};
+use crate::derivation::{self, Kind};
+use crate::hashed::Hashed;
+use crate::pace::Pacer;
+use crate::rc::addr::WeakAddr;
+use crate::rc::dependencies::Dependencies;
+use crate::rc::value::{Access, Value};
+use crate::rc::{batch, Derived, Evaluation, Invalid, Observable, State, Version};
+use crate::retry::Retry;
+use crate::spawner::LocalSpawner;
+
+/// Single-threaded async derived value. The tracked `handler` produces
+/// a key, and every new key starts a `!Send` future on a local executor.
+pub struct Async<T, E = Infallible>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	body: Rc<AsyncBody<T, E>>,
+}
+
+impl<T, E> Clone for Async<T, E>
+where
+	T: Hash,
+{
+	fn clone(&self) -> Self {
+		Self {
+			body: self.body.clone(),
+		}
+	}
+}
+
+impl<T: Hash + 'static, E: 'static> From<Async<T, E>> for Rc<dyn Any> {
+	fn from(var: Async<T, E>) -> Self {
+		var.body
+	}
+}
+
+impl<T: Hash + 'static, E: 'static> TryFrom<Rc<dyn Any>> for Async<T, E> {
+	type Error = Rc<dyn Any>;
+	fn try_from(value: Rc<dyn Any>) -> Result<Self, Self::Error> {
+		Rc::downcast::<AsyncBody<T, E>>(value).map(|body| Async { body })
+	}
+}
+
+pub struct AsyncBody<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	value: RefCell<AsyncState<T, E>>,
+	inner: RefCell<AsyncInner<T, E>>,
+}
+
+struct AsyncEffect<
+	K: Hash,
+	T,
+	E,
+	H: Fn(&Evaluation) -> K + 'static,
+	F: Fn(&K, CancellationToken) -> LocalBoxFuture<'static, Result<T, E>> + 'static,
+> {
+	handler: H,
+	func: F,
+	value: Option<Hashed<K>>,
+}
+
+pub trait AsyncEffecty<T, E> {
+	fn compute(&mut self, cx: &Evaluation) -> u64;
+	fn invoke(&mut self, cancel: CancellationToken) -> LocalBoxFuture<'static, Result<T, E>>;
+}
+
+impl<K, T, E, H, F> AsyncEffecty<T, E> for AsyncEffect<K, T, E, H, F>
+where
+	K: Hash,
+	H: Fn(&Evaluation) -> K + 'static,
+	F: Fn(&K, CancellationToken) -> LocalBoxFuture<'static, Result<T, E>> + 'static,
+{
+	fn compute(&mut self, cx: &Evaluation) -> u64 {
+		let value = Hashed::new((self.handler)(cx));
+		self.value = Some(value);
+		self.value.as_ref().unwrap().hash
+	}
+
+	fn invoke(&mut self, cancel: CancellationToken) -> LocalBoxFuture<'static, Result<T, E>> {
+		(self.func)(&self.value.as_ref().unwrap().value, cancel)
+	}
+}
+
+pub struct AsyncInner<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	effect: Box<dyn AsyncEffecty<T, E>>,
+	revision: Option<u64>,
+	version: u64,
+	failures: u64,
+	cancel: CancellationToken,
+	pacer: Pacer,
+	retry: Option<Retry<E>>,
+	attempt: u32,
+	refetch_interval: Option<Duration>,
+	stale: bool,
+	handle: Option<AbortHandle>,
+	spawner: Option<Rc<dyn LocalSpawner>>,
+	state: State,
+	used_by: BTreeSet<WeakAddr<dyn Derived>>,
+	dependencies: Dependencies,
+	/// Name shown when the value writes a `Var` while it evaluates.
+	name: &'static str,
+	/// Distance from the sources of the graph, as of the last evaluation.
+	depth: u32,
+	this: Weak<AsyncBody<T, E>>,
+}
+
+impl<T, E> Drop for AsyncInner<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	fn drop(&mut self) {
+		self.abort();
+
+		let refr = self.this.clone() as Weak<dyn Derived>;
+		self.dependencies.drop(&refr);
+	}
+}
+
+impl<T> Async<T>
+where
+	T: Hash + 'static,
+{
+	pub fn new<K: Hash + 'static>(
+		handler: impl Fn(&Evaluation) -> K + 'static,
+		func: impl Fn(&K, CancellationToken) -> LocalBoxFuture<'static, T> + 'static,
+	) -> Self {
+		Async::new_with_name("<unnamed>", handler, func)
+	}
+
+	/// Same as [`Async::new`], named in the reports of its `Var` writes.
+	pub fn new_with_name<K: Hash + 'static>(
+		name: &'static str,
+		handler: impl Fn(&Evaluation) -> K + 'static,
+		func: impl Fn(&K, CancellationToken) -> LocalBoxFuture<'static, T> + 'static,
+	) -> Self {
+		Async::new_fallible_with_name(name, handler, move |key, cancel| {
+			func(key, cancel).map(Ok).boxed_local()
+		})
+	}
+}
+
+impl<T, E> Async<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	/// Same as [`Async::new`], but the future can fail. A failure is
+	/// reported as [`AsyncState::Failed`] and keeps the last good value.
+	pub fn new_fallible<K: Hash + 'static>(
+		handler: impl Fn(&Evaluation) -> K + 'static,
+		func: impl Fn(&K, CancellationToken) -> LocalBoxFuture<'static, Result<T, E>> + 'static,
+	) -> Self {
+		Async::new_fallible_with_name("<unnamed>", handler, func)
+	}
+
+	/// Same as [`Async::new_fallible`], named in the reports of its `Var` writes.
+	pub fn new_fallible_with_name<K: Hash + 'static>(
+		name: &'static str,
+		handler: impl Fn(&Evaluation) -> K + 'static,
+		func: impl Fn(&K, CancellationToken) -> LocalBoxFuture<'static, Result<T, E>> + 'static,
+	) -> Self {
+		Async {
+			body: Rc::new_cyclic(|this| AsyncBody {
+				value: RefCell::new(AsyncState::Pending),
+				inner: RefCell::new(AsyncInner {
+					effect: Box::new(AsyncEffect {
+						func,
+						handler,
+						value: None,
+					}) as Box<dyn AsyncEffecty<T, E>>,
+					revision: None,
+					version: AsyncState::<T, E>::Pending.fingerprint(0),
+					failures: 0,
+					handle: None,
+					spawner: None,
+					cancel: CancellationToken::new(),
+					pacer: Pacer::default(),
+					retry: None,
+					attempt: 0,
+					refetch_interval: None,
+					stale: false,
+					state: State::Invalid(Invalid::Definitely),
+					used_by: BTreeSet::new(),
+					dependencies: Dependencies::new(),
+					name,
+					depth: 0,
+					this: this.clone(),
+				}),
+			}),
+		}
+	}
+
+	/// Runs futures of this value with `spawner` instead of
+	/// the [default](crate::spawner::default_local) one.
+	pub fn with_spawner(self, spawner: impl LocalSpawner + 'static) -> Self {
+		self.body.inner.borrow_mut().spawner = Some(Rc::new(spawner));
+		self
+	}
+
+	/// Starts a run only once the inputs stayed the same for `duration`.
+	/// The inputs are still tracked right away, the state shows loading.
+	/// Waiting uses tokio time, so runs need the tokio timer.
+	pub fn with_debounce(self, duration: Duration) -> Self {
+		self.body.inner.borrow_mut().pacer.debounce(duration);
+		self
+	}
+
+	/// Starts at most one run per `duration`, with the latest inputs.
+	/// Waiting uses tokio time, so runs need the tokio timer.
+	pub fn with_throttle(self, duration: Duration) -> Self {
+		self.body.inner.borrow_mut().pacer.throttle(duration);
+		self
+	}
+
+	/// Runs the future again when it fails, as long as `retry` allows.
+	/// Scheduled retries are reported as [`AsyncState::Retrying`].
+	pub fn with_retry(self, retry: Retry<E>) -> Self {
+		self.body.inner.borrow_mut().retry = Some(retry);
+		self
+	}
+
+	/// Runs the future again every `interval` after it finished, while
+	/// the value is observed. An unobserved value is refetched on the
+	/// next tracked read. Waiting uses tokio time, so refetches need the tokio timer.
+	pub fn with_refetch_interval(self, interval: Duration) -> Self {
+		self.body.inner.borrow_mut().refetch_interval = Some(interval);
+		self
+	}
+
+	/// Starts a new run with the current inputs, the running one is cancelled.
+	#[inline]
+	pub fn refresh(&self) {
+		self.body.refresh()
+	}
+
+	#[inline]
+	pub fn state_once(&self) -> Ref<'_, AsyncState<T, E>> {
+		self.body.state_once()
+	}
+
+	#[inline]
+	pub fn state<'a>(&'a self, cx: &'a impl AsRef<Evaluation>) -> Ref<'a, AsyncState<T, E>> {
+		self.body.state(cx.as_ref())
+	}
+
+	/// Same as [`Async::get`], but fails with [`NotReady`] while there is
+	/// no value, so derivations can short-circuit with `?`. A first run
+	/// that failed is reported with its error instead of as pending.
+	#[inline]
+	pub fn read(&self, cx: &impl AsRef<Evaluation>) -> Result<T, NotReady<E>>
+	where
+		T: Clone,
+		E: Clone,
+	{
+		self.body.state(cx.as_ref()).read().cloned()
+	}
+
+	/// The latest known value, fresh or stale, without tracking.
+	#[inline]
+	pub fn get_once(&self) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.body.state_once().value().cloned()
+	}
+
+	/// The latest known value, fresh or stale.
+	#[inline]
+	pub fn get(&self, cx: &impl AsRef<Evaluation>) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.body.state(cx.as_ref()).value().cloned()
+	}
+}
+
+impl<T, E> AsyncBody<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	pub fn state_once(&self) -> Ref<'_, AsyncState<T, E>> {
+		self.inner_update(&mut self.inner.borrow_mut());
+		self.value.borrow()
+	}
+
+	pub fn state(&self, eval: &'_ Evaluation) -> Ref<'_, AsyncState<T, E>> {
+		{
+			let mut self_mut = self.inner.borrow_mut();
+			self.inner_update(&mut self_mut);
+			if self_mut.stale {
+				self.restart(&mut self_mut, None);
+			}
+
+			eval.based_on(
+				self_mut.this.upgrade().unwrap(),
+				Version::Hash(self_mut.version),
+			);
+			self_mut.used_by(eval.parent());
+		}
+
+		self.value.borrow()
+	}
+
+	pub(crate) fn used_by(&self, observable: Weak<dyn Derived>) {
+		self.inner.borrow_mut().used_by(observable);
+	}
+
+	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
+		self.inner.borrow_mut().not_used_by(derived);
+	}
+
+	pub fn inner_update(&self, inner_mut: &mut AsyncInner<T, E>) {
+		if inner_mut.state == State::Valid {
+			return;
+		}
+
+		let is_valid = match inner_mut.state {
+			State::Valid => true,
+			State::Invalid(Invalid::Definitely) => false,
+			State::Invalid(Invalid::Maybe) => inner_mut.dependencies.are_valid(),
+		};
+
+		if is_valid {
+			inner_mut.state = State::Valid;
+			return;
+		}
+
+		let this = inner_mut.this.clone();
+		let evaluation = Evaluation::new(this.clone() as Weak<dyn Derived>);
+		let addr = derivation::addr(inner_mut.this.as_ptr());
+		let revision = derivation::run(Kind::Computed, inner_mut.name, addr, || {
+			inner_mut.effect.compute(&evaluation)
+		});
+
+		if Some(revision) != inner_mut.revision {
+			inner_mut.revision = Some(revision);
+
+			// observers were already invalidated together with this node,
+			// they will pick up the new version when they re-read it
+			let start = inner_mut.pacer.schedule();
+			self.restart(inner_mut, start);
+		}
+
+		inner_mut.state = State::Valid;
+
+		let parent = inner_mut.this.clone() as Weak<dyn Derived>;
+		inner_mut.dependencies.swap(evaluation.take(), &parent);
+		inner_mut.depth = inner_mut.dependencies.depth();
+	}
+
+	pub fn refresh(&self) {
+		let mut inner = self.inner.borrow_mut();
+		let cancel = inner.cancel.clone();
+		self.inner_update(&mut inner);
+
+		// new inputs already started a fresh run
+		if cancel.is_cancelled() {
+			return;
+		}
+
+		if self.restart(&mut inner, None) {
+			Self::notify(inner);
+		}
+	}
+
+	/// Starts a new run for the current key, the previous one becomes stale.
+	/// Returns whether the state changed.
+	fn restart(&self, inner: &mut AsyncInner<T, E>, start: Option<Instant>) -> bool {
+		inner.abort();
+		let changed = self.transition(inner, AsyncState::refreshing);
+
+		inner.cancel = CancellationToken::new();
+		inner.attempt = 1;
+		inner.stale = false;
+
+		self.run(inner, start);
+		changed
+	}
+
+	/// Spawns the future for the current key, delayed until `start` if given.
+	fn run(&self, inner: &mut AsyncInner<T, E>, start: Option<Instant>) {
+		let this = inner.this.clone();
+		let cancel = inner.cancel.clone();
+
+		// delayed runs read the key only once they start,
+		// so keys skipped by debouncing never reach `func`
+		let future = match start {
+			Some(_) => None,
+			None => Some(inner.effect.invoke(cancel.clone())),
+		};
+
+		inner.spawn(async move {
+			let future = match future {
+				Some(future) => future,
+				None => {
+					if let Some(start) = start {
+						tokio::time::sleep_until(start).await;
+					}
+
+					let Some(body) = this.upgrade() else {
+						return;
+					};
+
+					let mut inner = body.inner.borrow_mut();
+					if cancel.is_cancelled() {
+						return;
+					}
+
+					let future = inner.effect.invoke(cancel.clone());
+
+					// a retry is in flight from now on, no longer scheduled
+					if body.transition(&mut inner, AsyncState::refreshing) {
+						Self::notify(inner);
+					}
+
+					future
+				}
+			};
+
+			let result = future.await;
+			let Some(this) = this.upgrade() else {
+				return;
+			};
+
+			this.resolve(&cancel, result);
+		});
+	}
+
+	/// Moves the stored state forward and returns whether its version changed.
+	fn transition(
+		&self,
+		inner: &mut AsyncInner<T, E>,
+		func: impl FnOnce(AsyncState<T, E>) -> AsyncState<T, E>,
+	) -> bool {
+		let mut value = self.value.borrow_mut();
+		*value = func(std::mem::take(&mut *value));
+
+		if matches!(*value, AsyncState::Failed(..) | AsyncState::Retrying(..)) {
+			inner.failures += 1;
+		}
+
+		let version = value.fingerprint(inner.failures);
+		let changed = version != inner.version;
+		inner.version = version;
+		changed
+	}
+
+	fn resolve(&self, cancel: &CancellationToken, result: Result<T, E>) {
+		let mut inner = self.inner.borrow_mut();
+
+		// the run was superseded while it was finishing
+		if cancel.is_cancelled() {
+			return;
+		}
+
+		inner.handle = None;
+
+		let retry = match &result {
+			Err(error) => inner
+				.retry
+				.as_ref()
+				.and_then(|retry| retry.delay(error, inner.attempt)),
+			Ok(_) => None,
+		};
+
+		let changed = match (retry, result) {
+			(Some(delay), Err(error)) => {
+				inner.attempt += 1;
+				let attempt = Attempt {
+					number: inner.attempt,
+					at: Instant::now() + delay,
+				};
+
+				let changed = self.transition(&mut inner, |state| state.retrying(error, attempt));
+				self.run(&mut inner, Some(attempt.at));
+				changed
+			}
+			(_, result) => {
+				let changed = self.transition(&mut inner, |state| state.resolve(result));
+				self.schedule_refetch(&mut inner);
+				changed
+			}
+		};
+
+		if changed {
+			Self::notify(inner);
+		}
+	}
+
+	/// Plans the next periodic run, if the value is configured for it.
+	fn schedule_refetch(&self, inner: &mut AsyncInner<T, E>) {
+		let Some(interval) = inner.refetch_interval else {
+			return;
+		};
+
+		let this = inner.this.clone();
+		let cancel = inner.cancel.clone();
+		inner.spawn(async move {
+			tokio::time::sleep(interval).await;
+			if let Some(this) = this.upgrade() {
+				this.refetch(&cancel);
+			}
+		});
+	}
+
+	fn refetch(&self, cancel: &CancellationToken) {
+		let mut inner = self.inner.borrow_mut();
+
+		// new inputs or a refresh started another run meanwhile
+		if cancel.is_cancelled() {
+			return;
+		}
+
+		inner.handle = None;
+
+		if inner.observers().is_empty() {
+			// nobody is looking, the next tracked read refetches
+			inner.stale = true;
+			return;
+		}
+
+		if self.restart(&mut inner, None) {
+			Self::notify(inner);
+		}
+	}
+
+	/// Invalidates the observers after the state changed outside of an update.
+	fn notify(mut inner: RefMut<'_, AsyncInner<T, E>>) {
+		let used_by = inner.observers();
+		std::mem::drop(inner);
+
+		// only invalidating deps, not the value itself,
+		// reactions re-read it once the borrow is released
+		batch(|| {
+			for item in used_by {
+				item.invalidate(Invalid::Maybe);
+			}
+		});
+	}
+}
+
+impl<T, E> AsyncInner<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	pub(crate) fn used_by(&mut self, observable: Weak<dyn Derived>) {
+		self.used_by.insert(WeakAddr::new(observable));
+	}
+
+	fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+		let (future, handle) = futures::future::abortable(future);
+		self.handle = Some(handle);
+
+		let spawner = self
+			.spawner
+			.clone()
+			.unwrap_or_else(crate::spawner::default_local);
+		spawner.spawn_local(future.map(|_| ()).boxed_local());
+	}
+
+	/// Cancels the token handed to the running future
+	/// and aborts its task.
+	fn abort(&mut self) {
+		self.cancel.cancel();
+		if let Some(handle) = self.handle.take() {
+			handle.abort();
+		}
+	}
+
+	fn not_used_by(&mut self, derived: &Weak<dyn Derived>) {
+		self.used_by.remove(&WeakAddr::new(derived.clone()));
+	}
+
+	/// Live observers, forgetting the dropped ones.
+	fn observers(&mut self) -> Vec<Rc<dyn Derived>> {
+		let mut observers = Vec::with_capacity(self.used_by.len());
+		self.used_by.retain(|item| match item.upgrade() {
+			Some(item) => {
+				observers.push(item);
+				true
+			}
+			None => false,
+		});
+		observers
+	}
+}
+
+impl<T, E> Observable for AsyncBody<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	fn update(&self) -> Version {
+		let mut inner = self.inner.borrow_mut();
+		self.inner_update(&mut inner);
+		Version::Hash(inner.version)
+	}
+
+	fn version(&self) -> Version {
+		Version::Hash(self.inner.borrow().version)
+	}
+
+	fn used_by(&self, derived: Weak<dyn Derived>) {
+		AsyncBody::used_by(self, derived)
+	}
+
+	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
+		AsyncBody::not_used_by(self, derived)
+	}
+
+	fn depth(&self) -> u32 {
+		self.inner.borrow().depth
+	}
+}
+
+impl<T, E> Access<AsyncState<T, E>> for AsyncBody<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	fn get(&self, tracker: &Evaluation) -> crate::rc::value::Ref<'_, AsyncState<T, E>> {
+		crate::rc::value::Ref::Cell(self.state(tracker))
+	}
+
+	fn get_once(&self) -> crate::rc::value::Ref<'_, AsyncState<T, E>> {
+		crate::rc::value::Ref::Cell(self.state_once())
+	}
+}
+
+impl<T, E> Derived for AsyncBody<T, E>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	fn invalidate(self: Rc<Self>, invalid: crate::rc::Invalid) {
+		let mut self_mut = self.inner.borrow_mut();
+		if matches!(self_mut.state, State::Valid) {
+			self_mut.state = State::Invalid(invalid);
+			self_mut.used_by.retain(|item| {
+				if let Some(item) = item.upgrade() {
+					item.invalidate(Invalid::Maybe);
+					true
+				} else {
+					false
+				}
+			});
+		}
+	}
+}
+
+impl<T, E> From<Async<T, E>> for Value<AsyncState<T, E>>
+where
+	T: Hash + 'static,
+	E: 'static,
+{
+	fn from(value: Async<T, E>) -> Self {
+		Value::new(value.body)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::Cell;
+
+	use futures::executor::LocalPool;
+
+	use super::*;
+	use crate::rc::{batch, Reaction, Var};
+
+	#[test]
+	fn runs_local_futures() {
+		let mut pool = LocalPool::new();
+		let a = Var::new(1);
+
+		// `Rc` inside the future makes it `!Send`
+		let b = Async::new(
+			{
+				let a = a.clone();
+				move |cx| a.get(cx)
+			},
+			|&value, _| {
+				let value = Rc::new(value);
+				async move { *value * 2 }.boxed_local()
+			},
+		)
+		.with_spawner(pool.spawner());
+
+		let seen = Rc::new(Cell::new(None));
+		let reaction = Reaction::new(Box::new({
+			let b = b.clone();
+			let seen = seen.clone();
+			move |cx| seen.set(b.get(cx))
+		}));
+
+		reaction.update();
+		assert_eq!(seen.get(), None);
+
+		pool.run_until_stalled();
+		assert_eq!(seen.get(), Some(2));
+
+		batch(|| a.set(2));
+		assert_eq!(*b.state_once(), AsyncState::Refreshing(2));
+
+		pool.run_until_stalled();
+		assert_eq!(seen.get(), Some(4));
+	}
+}
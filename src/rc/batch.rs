@@ -1,10 +1,12 @@
 use std::cell::Cell;
 
+use crate::config;
 use crate::rc::reaction::CHANGED;
+use crate::rounds::Rounds;
 
 thread_local! {
-	pub(crate) static STARTED: Cell<bool> = Cell::new(false);
-	pub(crate) static MICROTASK: Cell<bool> = Cell::new(false);
+	pub(crate) static STARTED: Cell<bool> = const { Cell::new(false) };
+	pub(crate) static MICROTASK: Cell<bool> = const { Cell::new(false) };
 }
 
 pub fn in_batch() -> bool {
@@ -53,26 +55,51 @@ fn batch_stop() {
 	STARTED.with(|s| s.set(false));
 }
 
+/// Runs the queued reactions in rounds until no new ones get queued.
+/// The batch stays open meanwhile, so reactions queued by a round
+/// run in the next one.
 pub fn batch_run() {
-	loop {
-		let changed = {
-			let mut borrow = unsafe { CHANGED.borrow_mut() };
-			let items = std::mem::replace(&mut *borrow, Vec::new());
-			std::mem::drop(borrow);
+	struct Stop;
 
-			items
-		};
+	impl Drop for Stop {
+		fn drop(&mut self) {
+			batch_stop();
+		}
+	}
+
+	let _stop = batch_start().then(|| Stop);
+	let mut rounds = Rounds::new(config::max_rounds());
+
+	loop {
+		let changed = CHANGED.with(|changed| std::mem::take(&mut *changed.borrow_mut()));
 
-		if changed.len() == 0 {
+		if changed.is_empty() {
 			break;
 		}
 
-		// if let Ok(mut changed) = changed {
-		for reaction in changed {
+		let Some(traced) = rounds.start() else {
+			// the reactions left behind run again on the next change
+			for reaction in changed.into_values() {
+				if let Some(reactive) = reaction.upgrade() {
+					reactive.dequeue();
+				}
+			}
+			rounds.diverged();
+		};
+
+		let mut names = Vec::new();
+		for reaction in changed.into_values() {
 			if let Some(reactive) = reaction.upgrade() {
+				if traced {
+					names.push(reactive.name());
+				}
 				reactive.update();
 			}
 		}
+
+		if traced {
+			rounds.finish(names);
+		}
 	}
 }
 
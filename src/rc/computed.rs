@@ -1,10 +1,12 @@
 use std::any::Any;
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::rc::{Rc, Weak};
 
+use crate::config;
+use crate::derivation::{self, Kind};
 use crate::hashed::Hashed;
 use crate::rc::addr::WeakAddr;
 use crate::rc::dependencies::Dependencies;
@@ -47,6 +49,9 @@ where
 	T: Hash + 'static,
 {
 	value: RefCell<Option<Hashed<T>>>,
+	depth: Cell<u32>,
+	/// Set when a dependency changed while the computed evaluated.
+	stale: Cell<bool>,
 	inner: RefCell<ComputedInner<T>>,
 }
 
@@ -55,6 +60,7 @@ where
 	T: Hash + 'static,
 {
 	func: Box<dyn Fn(&Evaluation) -> T>,
+	pub(crate) name: &'static str,
 	state: State,
 	used_by: BTreeSet<WeakAddr<dyn Derived>>,
 	dependencies: Dependencies,
@@ -76,11 +82,18 @@ where
 	T: Hash + 'static,
 {
 	pub fn new(func: Box<dyn Fn(&Evaluation) -> T>) -> Self {
+		Self::new_with_name("<unnamed>", func)
+	}
+
+	pub fn new_with_name(name: &'static str, func: Box<dyn Fn(&Evaluation) -> T>) -> Self {
 		Computed {
 			body: Rc::new_cyclic(|this| ComputedBody {
 				value: RefCell::new(None),
+				depth: Cell::new(0),
+				stale: Cell::new(false),
 				inner: RefCell::new(ComputedInner {
 					func,
+					name,
 					state: State::Invalid(Invalid::Definitely),
 					used_by: BTreeSet::new(),
 					dependencies: Dependencies::new(),
@@ -106,6 +119,13 @@ where
 	T: Hash + 'static,
 {
 	pub fn get_once(&self) -> Ref<'_, T> {
+		if config::warn_untracked_reads() && derivation::current().is_none() {
+			tracing::warn!(
+				computed = self.inner.borrow().name,
+				"Computed was read outside of any computed or reaction"
+			);
+		}
+
 		self.update();
 		Ref::map(
 			Ref::map(self.value.borrow(), |s| s.as_ref().unwrap()),
@@ -155,11 +175,19 @@ where
 
 		let this = inner_mut.this.clone() as Weak<dyn Derived>;
 		let evaluation = Evaluation::new(this);
-		let value = (inner_mut.func)(&evaluation);
-		inner_mut.state = State::Valid;
+		let addr = derivation::addr(inner_mut.this.as_ptr());
+		let value = derivation::run(Kind::Computed, inner_mut.name, addr, || {
+			(inner_mut.func)(&evaluation)
+		});
+		inner_mut.state = if self.stale.take() {
+			State::Invalid(Invalid::Definitely)
+		} else {
+			State::Valid
+		};
 
 		let parent = inner_mut.this.clone() as Weak<dyn Derived>;
 		inner_mut.dependencies.swap(evaluation.take(), &parent);
+		self.depth.set(inner_mut.dependencies.depth());
 
 		*self.value.borrow_mut() = Some(Hashed::new(value));
 	}
@@ -198,6 +226,10 @@ where
 	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
 		ComputedBody::not_used_by(self, derived)
 	}
+
+	fn depth(&self) -> u32 {
+		self.depth.get()
+	}
 }
 
 impl<T> Access<T> for ComputedBody<T>
@@ -218,6 +250,13 @@ where
 	T: Hash + 'static,
 {
 	fn invalidate(self: Rc<Self>, invalid: crate::rc::Invalid) {
+		// the computed wrote to one of its own dependencies and is borrowed,
+		// so it evaluates again on the next read
+		if derivation::is_running(derivation::addr(Rc::as_ptr(&self))) {
+			self.stale.set(true);
+			return;
+		}
+
 		let mut self_mut = self.inner.borrow_mut();
 		if matches!(self_mut.state, State::Valid) {
 			self_mut.state = State::Invalid(invalid);
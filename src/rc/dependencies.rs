@@ -4,18 +4,11 @@ use std::rc::{Rc, Weak};
 use crate::rc::addr::RcAddr;
 use crate::rc::{Derived, Observable, Version};
 
+#[derive(Default)]
 pub struct Dependencies {
 	based_on: BTreeMap<RcAddr<dyn Observable>, Version>,
 }
 
-impl Default for Dependencies {
-	fn default() -> Self {
-		Dependencies {
-			based_on: BTreeMap::new(),
-		}
-	}
-}
-
 impl Dependencies {
 	pub fn new() -> Self {
 		Self {
@@ -24,8 +17,8 @@ impl Dependencies {
 	}
 
 	pub fn drop(&mut self, parent: &Weak<dyn Derived>) {
-		for (item, _) in &self.based_on {
-			item.not_used_by(&parent)
+		for item in self.based_on.keys() {
+			item.not_used_by(parent)
 		}
 	}
 
@@ -43,12 +36,21 @@ impl Dependencies {
 		true
 	}
 
+	/// One more than the deepest dependency, zero without any.
+	pub fn depth(&self) -> u32 {
+		self.based_on
+			.keys()
+			.map(|base| base.depth() + 1)
+			.max()
+			.unwrap_or(0)
+	}
+
 	pub fn swap(&mut self, next: Dependencies, parent: &Weak<dyn Derived>) {
 		let prev = std::mem::replace(&mut self.based_on, next.based_on);
 
 		// Diff the keys
 		prev.keys()
 			.filter(|k| !self.based_on.contains_key(k))
-			.for_each(|k| k.not_used_by(&parent));
+			.for_each(|k| k.not_used_by(parent));
 	}
 }
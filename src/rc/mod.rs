@@ -1,6 +1,7 @@
 pub mod macros;
 
 mod addr;
+mod r#async;
 mod batch;
 mod computed;
 mod r#const;
@@ -15,11 +16,13 @@ mod microtask;
 
 use std::rc::{Rc, Weak};
 
+pub use crate::async_state::{AsyncState, Attempt, NotReady, Pending};
 pub use batch::{batch, batch_microtask, in_batch};
 pub use computed::Computed;
 pub use dependencies::Dependencies;
 pub use evaluation::Evaluation;
-pub use reaction::{Reaction, Reactions, Reactive, CHANGED};
+pub use r#async::Async;
+pub use reaction::{Reaction, Reactions, Reactive};
 pub use value::Value;
 pub use var::Var;
 
@@ -43,6 +46,12 @@ pub trait Observable: 'static {
 	/// Notify this observable that `derived` stopped
 	/// to listen.
 	fn not_used_by(&self, derived: &Weak<dyn Derived>);
+
+	/// Distance from the sources of the graph, as of the
+	/// last evaluation. Reactions run in order of depth.
+	fn depth(&self) -> u32 {
+		0
+	}
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
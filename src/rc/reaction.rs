@@ -1,15 +1,57 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell, RefMut};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::rc::{Rc, Weak};
 
-use crate::rc::batch::in_batch;
+use crate::derivation::{self, Kind};
+use crate::rc::batch::{batch, in_batch};
 use crate::rc::dependencies::Dependencies;
 use crate::rc::{Derived, Evaluation, Invalid, State};
 
 pub trait Reactive {
 	fn update(&self);
+
+	/// Name shown when reactions do not settle.
+	fn name(&self) -> &'static str {
+		"<unnamed>"
+	}
+
+	/// Called when a batch dropped it from its queue because the reactions
+	/// did not settle. It forgets the pending run, so that the next change
+	/// of a dependency queues it again.
+	fn dequeue(&self) {}
+}
+
+/// Position of a reaction in the queue of a batch: higher priorities
+/// run first, then shallower reactions, then the ones created earlier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Order {
+	priority: Reverse<i32>,
+	depth: u32,
+	created: u64,
 }
 
-pub static mut CHANGED: RefCell<Vec<Weak<dyn Reactive>>> = RefCell::new(vec![]);
+thread_local! {
+	/// Reactions to run when the batch ends, each one at most once.
+	pub(crate) static CHANGED: RefCell<BTreeMap<Order, Weak<dyn Reactive>>> =
+		const { RefCell::new(BTreeMap::new()) };
+	static CREATED: Cell<u64> = const { Cell::new(0) };
+}
+
+impl Order {
+	pub(crate) fn new(priority: i32, depth: u32, created: u64) -> Self {
+		Order {
+			priority: Reverse(priority),
+			depth,
+			created,
+		}
+	}
+
+	/// Creation counter of reactions, to break ties between equal depths.
+	pub(crate) fn next_created() -> u64 {
+		CREATED.with(|created| created.replace(created.get() + 1))
+	}
+}
 
 #[derive(Default, Clone)]
 pub struct Reactions<const N: usize> {
@@ -40,14 +82,18 @@ pub struct Reaction {
 
 pub struct ReactionBody {
 	pub(crate) inner: RefCell<ReactionInner>,
+	/// Set when a dependency changed while the reaction ran.
+	stale: Cell<bool>,
 }
 
 pub struct ReactionInner {
 	state: State,
-	#[allow(unused)]
 	pub(crate) name: &'static str,
 	func: Box<dyn Fn(&Evaluation)>,
 	dependencies: Dependencies,
+	priority: i32,
+	depth: u32,
+	created: u64,
 	this: Weak<ReactionBody>,
 }
 
@@ -68,28 +114,31 @@ impl Reaction {
 	pub fn new_with_name(name: &'static str, func: Box<dyn Fn(&Evaluation)>) -> Self {
 		Reaction {
 			body: Rc::new_cyclic(|this| ReactionBody {
+				stale: Cell::new(false),
 				inner: RefCell::new(ReactionInner {
 					func,
 					name,
 					state: State::Invalid(Invalid::Definitely),
 					dependencies: Dependencies::new(),
+					priority: 0,
+					depth: 0,
+					created: Order::next_created(),
 					this: this.clone(),
 				}),
 			}),
 		}
 	}
 
-	pub fn update_unchecked(&self) {
-		// NOTE: this logic is shared with the Self::update
-
-		let mut self_mut = self.body.inner.borrow_mut();
-
-		let this = Rc::downgrade(&self.body) as Weak<dyn Derived>;
-		let tracker = Evaluation::new(this.clone());
-		(self_mut.func)(&tracker);
+	/// Runs this reaction before the ones with a lower priority in the same
+	/// batch, whatever their depth. The priority is zero by default.
+	#[must_use]
+	pub fn with_priority(self, priority: i32) -> Self {
+		self.body.inner.borrow_mut().priority = priority;
+		self
+	}
 
-		self_mut.dependencies.swap(tracker.take(), &this);
-		self_mut.state = State::Valid;
+	pub fn update_unchecked(&self) {
+		self.body.run(self.body.inner.borrow_mut());
 	}
 
 	pub fn update(&self) {
@@ -98,6 +147,9 @@ impl Reaction {
 }
 
 impl Reactive for ReactionBody {
+	fn name(&self) -> &'static str {
+		self.inner.borrow().name
+	}
 	fn update(&self) {
 		let mut self_mut = self.inner.borrow_mut();
 
@@ -112,19 +164,44 @@ impl Reactive for ReactionBody {
 			return;
 		}
 
-		// NOTE: this logic is shared with the Self::update_unchecked
+		self.run(self_mut);
+	}
 
+	fn dequeue(&self) {
+		self.inner.borrow_mut().state = State::Valid;
+	}
+}
+
+impl ReactionBody {
+	fn run(&self, mut self_mut: RefMut<'_, ReactionInner>) {
 		let this = self_mut.this.clone() as Weak<dyn Derived>;
 		let tracker = Evaluation::new(this.clone());
-		(self_mut.func)(&tracker);
+		let addr = derivation::addr(self_mut.this.as_ptr());
+		derivation::run(Kind::Reaction, self_mut.name, addr, || {
+			(self_mut.func)(&tracker)
+		});
 
 		self_mut.dependencies.swap(tracker.take(), &this);
+		self_mut.depth = self_mut.dependencies.depth();
 		self_mut.state = State::Valid;
+		std::mem::drop(self_mut);
+
+		// the reaction wrote to one of its own dependencies, so it runs again
+		if self.stale.take() {
+			if let Some(this) = this.upgrade() {
+				batch(|| this.invalidate(Invalid::Definitely));
+			}
+		}
 	}
 }
 
 impl Derived for ReactionBody {
 	fn invalidate(self: Rc<Self>, invalid: crate::rc::Invalid) {
+		if derivation::is_running(derivation::addr(Rc::as_ptr(&self))) {
+			self.stale.set(true);
+			return;
+		}
+
 		let mut self_mut = self.inner.borrow_mut();
 		if matches!(self_mut.state, State::Valid) {
 			if !in_batch() {
@@ -132,17 +209,23 @@ impl Derived for ReactionBody {
 			}
 
 			self_mut.state = State::Invalid(invalid);
+			let order = Order::new(self_mut.priority, self_mut.depth, self_mut.created);
 			std::mem::drop(self_mut);
 
-			unsafe {
-				CHANGED
-					.borrow_mut()
-					.push(Rc::downgrade(&self) as Weak<dyn Reactive>)
-			}
+			enqueue(order, Rc::downgrade(&self) as Weak<dyn Reactive>);
 		}
 	}
 }
 
+/// Queues `reactive` to run in `order` when the batch of this thread ends,
+/// or when the next one does outside of a batch. A reaction already queued
+/// at the same position is kept, so it runs once.
+pub(crate) fn enqueue(order: Order, reactive: Weak<dyn Reactive>) {
+	CHANGED.with(|changed| {
+		changed.borrow_mut().entry(order).or_insert(reactive);
+	});
+}
+
 impl std::fmt::Debug for Reaction {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		f.debug_struct("Reaction")
@@ -5,11 +5,13 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::rc::{Rc, Weak};
 
+use crate::config;
+use crate::derivation;
 use crate::hashed::Hashed;
 use crate::rc::addr::WeakAddr;
 use crate::rc::evaluation::Evaluation;
 use crate::rc::value::{Access, Value};
-use crate::rc::{Computed, Derived, Invalid, Observable, Version};
+use crate::rc::{batch, in_batch, Computed, Derived, Invalid, Observable, Version};
 
 pub struct Var<T> {
 	body: Rc<VarBody<T>>,
@@ -29,6 +31,7 @@ impl<T: 'static> TryFrom<Rc<dyn Any>> for Var<T> {
 }
 
 pub struct VarBody<T> {
+	name: &'static str,
 	value: RefCell<Hashed<T>>,
 	inner: RefCell<VarInner<T>>,
 }
@@ -70,11 +73,20 @@ where
 	T: 'static,
 {
 	pub fn new(value: T) -> Self
+	where
+		T: Hash,
+	{
+		Self::new_with_name("<unnamed>", value)
+	}
+
+	/// Creates a `Var` whose name shows up when reactions do not settle.
+	pub fn new_with_name(name: &'static str, value: T) -> Self
 	where
 		T: Hash,
 	{
 		Var {
 			body: Rc::new_cyclic(|this| VarBody {
+				name,
 				value: RefCell::new(Hashed::new(value)),
 				inner: RefCell::new(VarInner {
 					used_by: BTreeSet::new(),
@@ -127,7 +139,13 @@ where
 	where
 		T: Hash,
 	{
-		self.body.set(value)
+		config::write(
+			self.body.name,
+			derivation::addr(Rc::as_ptr(&self.body)),
+			in_batch,
+			|write| batch(write),
+			|| self.body.set(value),
+		)
 	}
 
 	#[inline]
@@ -143,7 +161,13 @@ where
 	where
 		T: Hash,
 	{
-		self.body.replace(value)
+		config::write(
+			self.body.name,
+			derivation::addr(Rc::as_ptr(&self.body)),
+			in_batch,
+			|write| batch(write),
+			|| self.body.replace(value),
+		)
 	}
 
 	#[inline]
@@ -151,7 +175,13 @@ where
 	where
 		T: Hash,
 	{
-		self.body.update(func)
+		config::write(
+			self.body.name,
+			derivation::addr(Rc::as_ptr(&self.body)),
+			in_batch,
+			|write| batch(write),
+			|| self.body.update(func),
+		)
 	}
 }
 
@@ -175,7 +205,7 @@ impl<T> VarBody<T> {
 		Ref::map(value, |v| &v.value)
 	}
 
-	pub fn update<'a>(&'a self, func: impl FnOnce(&mut T))
+	pub fn update(&self, func: impl FnOnce(&mut T))
 	where
 		T: 'static + Hash,
 	{
@@ -202,7 +232,7 @@ impl<T> VarBody<T> {
 			self.invalidate();
 		}
 
-		return old.value;
+		old.value
 	}
 
 	pub fn set(&self, value: T)
@@ -213,6 +243,8 @@ impl<T> VarBody<T> {
 	}
 
 	fn invalidate(&self) {
+		crate::rounds::record_write(self.name);
+
 		let mut self_mut = self.inner.borrow_mut();
 		self_mut.used_by.retain(|item| {
 			if let Some(item) = item.upgrade() {
@@ -253,11 +285,11 @@ impl<T: 'static> Observable for VarBody<T> {
 	}
 
 	fn used_by(&self, derived: Weak<dyn Derived>) {
-		VarBody::used_by(&self, derived)
+		VarBody::used_by(self, derived)
 	}
 
 	fn not_used_by(&self, derived: &Weak<dyn Derived>) {
-		VarBody::not_used_by(&self, derived)
+		VarBody::not_used_by(self, derived)
 	}
 }
 
@@ -270,7 +302,7 @@ where
 	}
 
 	fn get_once(&self) -> crate::rc::value::Ref<'_, T> {
-		crate::rc::value::Ref::Cell(VarBody::get_once(&self))
+		crate::rc::value::Ref::Cell(VarBody::get_once(self))
 	}
 }
 
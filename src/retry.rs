@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+type Predicate<E> = Box<dyn Fn(&E) -> bool + Send + Sync>;
+
+/// When and how often an `Async` runs its future again after a failure.
+///
+/// Delays grow exponentially from [`Retry::backoff`] up to
+/// [`Retry::max_backoff`], with a random part unless jitter is disabled.
+/// Waiting uses tokio time, so retries need the tokio timer.
+pub struct Retry<E> {
+	max_attempts: u32,
+	backoff: Duration,
+	max_backoff: Duration,
+	jitter: bool,
+	predicate: Option<Predicate<E>>,
+}
+
+impl<E> Retry<E> {
+	/// Runs the future at most `max_attempts` times in total.
+	pub fn new(max_attempts: u32) -> Self {
+		Retry {
+			max_attempts,
+			backoff: Duration::from_millis(100),
+			max_backoff: Duration::from_secs(30),
+			jitter: true,
+			predicate: None,
+		}
+	}
+
+	/// Delay before the first retry, doubled for every next one.
+	pub fn backoff(mut self, backoff: Duration) -> Self {
+		self.backoff = backoff;
+		self
+	}
+
+	pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+		self.max_backoff = max_backoff;
+		self
+	}
+
+	/// Picks every delay randomly between its half and its full value.
+	/// Enabled by default.
+	pub fn jitter(mut self, jitter: bool) -> Self {
+		self.jitter = jitter;
+		self
+	}
+
+	/// Retries only the errors `predicate` returns `true` for.
+	pub fn when(mut self, predicate: impl Fn(&E) -> bool + Send + Sync + 'static) -> Self {
+		self.predicate = Some(Box::new(predicate));
+		self
+	}
+
+	/// Delay before the next run after run number `attempt` failed
+	/// with `error`, `None` when it should not be retried.
+	pub(crate) fn delay(&self, error: &E, attempt: u32) -> Option<Duration> {
+		if attempt >= self.max_attempts {
+			return None;
+		}
+
+		if let Some(predicate) = &self.predicate {
+			if !predicate(error) {
+				return None;
+			}
+		}
+
+		let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+		let delay = self.backoff.saturating_mul(factor).min(self.max_backoff);
+
+		if self.jitter {
+			let half = delay / 2;
+			Some(half + half.mul_f64(fastrand::f64()))
+		} else {
+			Some(delay)
+		}
+	}
+}
@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// How many of the last rounds are described when a batch does not settle.
+const TRACED: usize = 3;
+
+thread_local! {
+	static WRITES: RefCell<Option<Vec<&'static str>>> = const { RefCell::new(None) };
+}
+
+/// Notes a change of the `Var` named `name` while a round is traced.
+pub(crate) fn record_write(name: &'static str) {
+	WRITES.with(|writes| {
+		if let Some(writes) = writes.borrow_mut().as_mut() {
+			if !writes.contains(&name) {
+				writes.push(name);
+			}
+		}
+	})
+}
+
+/// Runs `func` while a round is traced on another thread, returning the
+/// `Var`s it changed so that the thread of the batch can [`merge`] them.
+pub(crate) fn recorded<R>(func: impl FnOnce() -> R) -> (R, Vec<&'static str>) {
+	let outer = WRITES.with(|writes| writes.borrow_mut().replace(Vec::new()));
+	let result = func();
+	let writes = WRITES.with(|writes| std::mem::replace(&mut *writes.borrow_mut(), outer));
+	(result, writes.unwrap_or_default())
+}
+
+/// Notes the changes [recorded](recorded) by the workers of a round.
+pub(crate) fn merge(names: Vec<&'static str>) {
+	names.into_iter().for_each(record_write);
+}
+
+/// Counts the rounds of reactions of one batch and remembers
+/// the last ones, to explain a batch that does not settle.
+pub(crate) struct Rounds {
+	max: usize,
+	count: usize,
+	last: VecDeque<Round>,
+}
+
+struct Round {
+	reactions: Vec<&'static str>,
+	vars: Vec<&'static str>,
+}
+
+impl Rounds {
+	pub(crate) fn new(max: usize) -> Self {
+		Rounds {
+			max,
+			count: 0,
+			last: VecDeque::new(),
+		}
+	}
+
+	/// Starts the next round. `None` when past the limit,
+	/// otherwise whether the round is traced.
+	pub(crate) fn start(&mut self) -> Option<bool> {
+		self.count += 1;
+		if self.count > self.max {
+			return None;
+		}
+
+		let traced = self.count + TRACED > self.max;
+		if traced {
+			WRITES.with(|writes| *writes.borrow_mut() = Some(Vec::new()));
+		}
+
+		Some(traced)
+	}
+
+	/// Ends a traced round that ran `reactions`.
+	pub(crate) fn finish(&mut self, reactions: Vec<&'static str>) {
+		let vars = WRITES.with(|writes| writes.borrow_mut().take().unwrap_or_default());
+		self.last.push_back(Round { reactions, vars });
+	}
+
+	pub(crate) fn diverged(&self) -> ! {
+		let mut message = format!(
+			"Reactions did not settle after {} rounds, they keep invalidating each other.",
+			self.max
+		);
+
+		let first = self.max + 1 - self.last.len();
+		for (index, round) in self.last.iter().enumerate() {
+			let _ = write!(
+				message,
+				"\n  round {}: ran {:?}, changed {:?}",
+				first + index,
+				round.reactions,
+				round.vars
+			);
+		}
+
+		panic!("{message}")
+	}
+}
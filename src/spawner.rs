@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::executor::LocalPool;
+use futures::future::{BoxFuture, LocalBoxFuture};
+use futures::task::LocalSpawnExt;
+use futures::StreamExt;
+use parking_lot::RwLock;
+
+/// Runs the futures of async derived values.
+pub trait Spawner: Send + Sync {
+	fn spawn(&self, future: BoxFuture<'static, ()>);
+}
+
+/// Runs the `!Send` futures of single-threaded async derived values.
+pub trait LocalSpawner {
+	fn spawn_local(&self, future: LocalBoxFuture<'static, ()>);
+}
+
+static DEFAULT: RwLock<Option<Arc<dyn Spawner>>> = RwLock::new(None);
+
+thread_local! {
+	static DEFAULT_LOCAL: RefCell<Option<Rc<dyn LocalSpawner>>> = const { RefCell::new(None) };
+}
+
+/// Sets the spawner used by every `Async` that was not given its own.
+pub fn set_default(spawner: impl Spawner + 'static) {
+	*DEFAULT.write() = Some(Arc::new(spawner));
+}
+
+/// The spawner set with [`set_default`], or the platform one:
+/// tokio natively and `spawn_local` on wasm.
+pub fn default() -> Arc<dyn Spawner> {
+	if let Some(spawner) = &*DEFAULT.read() {
+		return spawner.clone();
+	}
+
+	#[cfg(target_arch = "wasm32")]
+	return Arc::new(WasmSpawner);
+
+	#[cfg(not(target_arch = "wasm32"))]
+	return Arc::new(TokioSpawner::default());
+}
+
+/// Sets the local spawner used on this thread by every `rc::Async`
+/// that was not given its own.
+pub fn set_default_local(spawner: impl LocalSpawner + 'static) {
+	DEFAULT_LOCAL.with(|default| *default.borrow_mut() = Some(Rc::new(spawner)));
+}
+
+/// The local spawner set with [`set_default_local`], or the platform one:
+/// `tokio::task::spawn_local` natively and `spawn_local` on wasm.
+pub fn default_local() -> Rc<dyn LocalSpawner> {
+	if let Some(spawner) = DEFAULT_LOCAL.with(|default| default.borrow().clone()) {
+		return spawner;
+	}
+
+	#[cfg(target_arch = "wasm32")]
+	return Rc::new(WasmSpawner);
+
+	#[cfg(not(target_arch = "wasm32"))]
+	return Rc::new(TokioSpawner::default());
+}
+
+/// Spawns onto a tokio runtime. Local futures are spawned with
+/// `tokio::task::spawn_local` and need to run inside a `LocalSet`.
+#[derive(Default, Clone)]
+pub struct TokioSpawner {
+	handle: Option<tokio::runtime::Handle>,
+}
+
+impl TokioSpawner {
+	/// Spawns onto the runtime the caller is running in, even when
+	/// futures are later started from outside of it.
+	pub fn current() -> Self {
+		TokioSpawner {
+			handle: Some(tokio::runtime::Handle::current()),
+		}
+	}
+}
+
+impl From<tokio::runtime::Handle> for TokioSpawner {
+	fn from(handle: tokio::runtime::Handle) -> Self {
+		TokioSpawner {
+			handle: Some(handle),
+		}
+	}
+}
+
+impl Spawner for TokioSpawner {
+	fn spawn(&self, future: BoxFuture<'static, ()>) {
+		match &self.handle {
+			Some(handle) => std::mem::drop(handle.spawn(future)),
+			None => std::mem::drop(tokio::spawn(future)),
+		}
+	}
+}
+
+impl LocalSpawner for TokioSpawner {
+	fn spawn_local(&self, future: LocalBoxFuture<'static, ()>) {
+		std::mem::drop(tokio::task::spawn_local(future))
+	}
+}
+
+impl LocalSpawner for futures::executor::LocalSpawner {
+	fn spawn_local(&self, future: LocalBoxFuture<'static, ()>) {
+		// the pool was dropped, nobody is going to run the future
+		let _ = LocalSpawnExt::spawn_local(self, future);
+	}
+}
+
+/// Spawns onto a `futures::executor::LocalPool`. Futures make
+/// progress whenever the pool is run by its owner.
+#[derive(Clone)]
+pub struct LocalPoolSpawner {
+	sender: UnboundedSender<BoxFuture<'static, ()>>,
+}
+
+impl LocalPoolSpawner {
+	pub fn new(pool: &LocalPool) -> Self {
+		let (sender, receiver) = mpsc::unbounded();
+		LocalSpawnExt::spawn_local(
+			&pool.spawner(),
+			receiver.for_each_concurrent(None, |future| future),
+		)
+		.expect("LocalPool is shut down");
+
+		LocalPoolSpawner { sender }
+	}
+}
+
+impl Spawner for LocalPoolSpawner {
+	fn spawn(&self, future: BoxFuture<'static, ()>) {
+		// the pool was dropped, nobody is going to run the future
+		let _ = self.sender.unbounded_send(future);
+	}
+}
+
+/// Spawns with `wasm_bindgen_futures::spawn_local`.
+#[cfg(target_arch = "wasm32")]
+#[derive(Default, Clone, Copy)]
+pub struct WasmSpawner;
+
+#[cfg(target_arch = "wasm32")]
+impl Spawner for WasmSpawner {
+	fn spawn(&self, future: BoxFuture<'static, ()>) {
+		wasm_bindgen_futures::spawn_local(future)
+	}
+}
+
+#[cfg(target_arch = "wasm32")]
+impl LocalSpawner for WasmSpawner {
+	fn spawn_local(&self, future: LocalBoxFuture<'static, ()>) {
+		wasm_bindgen_futures::spawn_local(future)
+	}
+}
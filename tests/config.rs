@@ -0,0 +1,172 @@
+//! The configuration is global to the process, so it gets a test binary
+//! of its own and one test that goes through the policies in turn.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use observe::arc;
+use observe::config::{Config, EnforceBatch};
+use observe::rc::{Computed, Reaction, Var};
+
+#[allow(dead_code)]
+mod mock;
+
+#[test]
+fn policies() {
+	let a = Var::new(1);
+	let doubled = a.map(|a| a * 2);
+	let reaction = Reaction::new(Box::new({
+		let doubled = doubled.clone();
+		move |cx| {
+			doubled.get(cx);
+		}
+	}));
+	reaction.update();
+
+	// observed writes outside of a batch panic by default
+	assert!(catch_unwind(AssertUnwindSafe(|| a.set(2))).is_err());
+
+	observe::configure(Config {
+		enforce_batch: EnforceBatch::Never,
+		..Config::default()
+	});
+	a.set(3);
+	assert_eq!(*doubled.get_once(), 6);
+
+	observe::configure(Config {
+		enforce_batch: EnforceBatch::Always,
+		..Config::default()
+	});
+	let unobserved = Var::new_with_name("unobserved", 1);
+	let payload = catch_unwind(AssertUnwindSafe(|| unobserved.set(2))).unwrap_err();
+	assert_eq!(
+		payload.downcast_ref::<String>().unwrap(),
+		"Var `unobserved` was written outside of the `batch` function"
+	);
+	observe::rc::batch(|| unobserved.set(2));
+	assert_eq!(unobserved.get_once(), 2);
+
+	observe::configure(Config {
+		forbid_computed_writes: true,
+		..Config::default()
+	});
+	let target = Var::new_with_name("target", 2);
+	let writer = Computed::new_with_name(
+		"writer",
+		Box::new({
+			let target = target.clone();
+			move |_| target.set(3)
+		}),
+	);
+	let payload = catch_unwind(AssertUnwindSafe(|| *writer.get_once())).unwrap_err();
+	assert_eq!(
+		payload.downcast_ref::<String>().unwrap(),
+		"Var `target` was written while Computed `writer` evaluates"
+	);
+	assert_eq!(target.get_once(), 2);
+
+	observe::configure(Config {
+		max_rounds: 3,
+		..Config::default()
+	});
+	let count = Var::new(0);
+	let counter = Reaction::new(Box::new({
+		let count = count.clone();
+		move |cx| {
+			let value = count.get(cx);
+			if value > 0 {
+				observe::rc::batch(|| count.set(value + 1));
+			}
+		}
+	}));
+	counter.update();
+	let payload = catch_unwind(AssertUnwindSafe(|| observe::rc::batch(|| count.set(1))));
+	let message = payload.unwrap_err().downcast::<String>().unwrap();
+	assert!(message.contains("did not settle after 3 rounds"));
+
+	observe::configure(Config {
+		warn_untracked_reads: true,
+		..Config::default()
+	});
+	let warnings = mock::warnings(|| {
+		let _ = doubled.get_once();
+	});
+	assert_eq!(
+		warnings,
+		vec!["Computed was read outside of any computed or reaction computed=<unnamed>"]
+	);
+	let warnings = mock::warnings(|| observe::rc::batch(|| a.set(4)));
+	assert!(warnings.is_empty());
+
+	observe::configure(Config::default());
+	arc_policies();
+}
+
+/// The same policies for the `arc` graph.
+fn arc_policies() {
+	let a = arc::Var::new(1);
+	let doubled = a.map(|a| a * 2);
+	let reaction = arc::Reaction::new(Box::new({
+		let doubled = doubled.clone();
+		move |cx| {
+			let _ = doubled.get(cx);
+		}
+	}));
+	reaction.update();
+
+	assert!(catch_unwind(AssertUnwindSafe(|| a.set(2))).is_err());
+
+	observe::configure(Config {
+		enforce_batch: EnforceBatch::Never,
+		..Config::default()
+	});
+	a.set(3);
+	assert_eq!(*doubled.get_once(), 6);
+
+	observe::configure(Config {
+		enforce_batch: EnforceBatch::Always,
+		..Config::default()
+	});
+	let unobserved = arc::Var::new_with_name("unobserved", 1);
+	let payload = catch_unwind(AssertUnwindSafe(|| unobserved.set(2))).unwrap_err();
+	assert_eq!(
+		payload.downcast_ref::<String>().unwrap(),
+		"Var `unobserved` was written outside of the `batch` function"
+	);
+	arc::batch(|| unobserved.set(2));
+	assert_eq!(unobserved.get_once(), 2);
+
+	observe::configure(Config {
+		forbid_computed_writes: true,
+		..Config::default()
+	});
+	let target = arc::Var::new_with_name("target", 2);
+	let writer = arc::Computed::new_with_name(
+		"writer",
+		Box::new({
+			let target = target.clone();
+			move |_| target.set(3)
+		}),
+	);
+	let payload = catch_unwind(AssertUnwindSafe(|| *writer.get_once())).unwrap_err();
+	assert_eq!(
+		payload.downcast_ref::<String>().unwrap(),
+		"Var `target` was written while Computed `writer` evaluates"
+	);
+	assert_eq!(target.get_once(), 2);
+
+	observe::configure(Config {
+		warn_untracked_reads: true,
+		..Config::default()
+	});
+	let warnings = mock::warnings(|| {
+		let _ = doubled.get_once();
+	});
+	assert_eq!(
+		warnings,
+		vec!["Computed was read outside of any computed or reaction computed=<unnamed>"]
+	);
+	let warnings = mock::warnings(|| arc::batch(|| a.set(4)));
+	assert!(warnings.is_empty());
+
+	observe::configure(Config::default());
+}
@@ -75,3 +75,793 @@ fn check_invalidation() {
 
 	mock.get().checkpoint();
 }
+
+#[test]
+fn arc_batches_are_per_thread() {
+	use std::sync::{Arc, Barrier, Mutex};
+	use std::thread;
+
+	use observe::arc;
+
+	let opened = Arc::new(Barrier::new(2));
+	let closed = Arc::new(Barrier::new(2));
+
+	let other = thread::spawn({
+		let (opened, closed) = (opened.clone(), closed.clone());
+		move || {
+			arc::batch(|| {
+				opened.wait();
+				closed.wait();
+			})
+		}
+	});
+
+	opened.wait();
+	assert!(!arc::in_batch());
+
+	let a = arc::Var::new(1);
+	let runs = Arc::new(Mutex::new(Vec::new()));
+
+	let reaction = arc::Reaction::new(Box::new({
+		let a = a.clone();
+		let runs = runs.clone();
+		move |cx| {
+			runs.lock()
+				.unwrap()
+				.push((a.get(cx), thread::current().id()))
+		}
+	}));
+
+	reaction.update();
+	arc::batch(|| a.set(2));
+
+	let current = thread::current().id();
+	assert_eq!(*runs.lock().unwrap(), vec![(1, current), (2, current)]);
+
+	closed.wait();
+	other.join().unwrap();
+}
+
+#[test]
+fn arc_runtimes_are_isolated() {
+	use std::sync::{Arc, Mutex};
+
+	use observe::arc;
+
+	let first = arc::Runtime::new();
+	let second = arc::Runtime::new();
+	let runs = Arc::new(Mutex::new(Vec::new()));
+
+	let reaction = |runtime: &arc::Runtime, var: &arc::Var<i32>| {
+		runtime.enter(|| {
+			let reaction = arc::Reaction::new(Box::new({
+				let var = var.clone();
+				let runs = runs.clone();
+				move |cx| runs.lock().unwrap().push(var.get(cx))
+			}));
+			reaction.update();
+			reaction
+		})
+	};
+
+	let a = arc::Var::new(1);
+	let b = arc::Var::new(10);
+	let _a = reaction(&first, &a);
+	let _b = reaction(&second, &b);
+
+	first.batch(|| {
+		a.set(2);
+		assert!(!second.in_batch());
+		assert!(!arc::in_batch());
+
+		// the inner batch runs its own reactions right away
+		second.batch(|| b.set(20));
+		assert_eq!(*runs.lock().unwrap(), vec![1, 10, 20]);
+	});
+
+	assert_eq!(*runs.lock().unwrap(), vec![1, 10, 20, 2]);
+}
+
+#[test]
+fn arc_writes_reach_reactions_of_other_runtimes() {
+	use std::sync::{Arc, Mutex};
+
+	use observe::arc;
+
+	let first = arc::Runtime::new();
+	let second = arc::Runtime::new();
+	let runs = Arc::new(Mutex::new(Vec::new()));
+
+	let var = arc::Var::new(1);
+	let reaction = arc::Reaction::new_in(
+		&second,
+		Box::new({
+			let (var, runs) = (var.clone(), runs.clone());
+			move |cx| runs.lock().unwrap().push(var.get(cx))
+		}),
+	);
+	reaction.update();
+
+	first.batch(|| {
+		var.set(2);
+		// queued in its own runtime, not run by this one
+		assert_eq!(*runs.lock().unwrap(), vec![1]);
+	});
+
+	assert_eq!(*runs.lock().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn reactions_run_in_order() {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	let a = Var::new(1);
+	let b = a.map(|a| a + 1);
+	let c = Computed::new(Box::new({
+		let b = b.clone();
+		move |cx| *b.get(cx) + 1
+	}));
+
+	let runs = Rc::new(RefCell::new(Vec::new()));
+	let reaction = |name: &'static str, read: Box<dyn Fn(&observe::rc::Evaluation)>| {
+		let runs = runs.clone();
+		let reaction = Reaction::new(Box::new(move |cx| {
+			read(cx);
+			runs.borrow_mut().push(name)
+		}));
+		reaction.update();
+		reaction
+	};
+
+	let _reactions = [
+		reaction(
+			"deep",
+			Box::new({
+				let c = c.clone();
+				move |cx| {
+					let _ = c.get(cx);
+				}
+			}),
+		),
+		reaction(
+			"middle",
+			Box::new({
+				let b = b.clone();
+				move |cx| {
+					let _ = b.get(cx);
+				}
+			}),
+		),
+		reaction(
+			"shallow",
+			Box::new({
+				let a = a.clone();
+				move |cx| {
+					let _ = a.get(cx);
+				}
+			}),
+		),
+		reaction(
+			"first",
+			Box::new({
+				let c = c.clone();
+				move |cx| {
+					let _ = c.get(cx);
+				}
+			}),
+		)
+		.with_priority(1),
+	];
+
+	runs.borrow_mut().clear();
+	batch(|| a.set(2));
+	assert_eq!(*runs.borrow(), vec!["first", "shallow", "middle", "deep"]);
+}
+
+#[test]
+fn arc_reactions_run_in_order() {
+	use std::sync::{Arc, Mutex};
+
+	use observe::arc;
+
+	let a = arc::Var::new(1);
+	let b = a.map(|a| a + 1);
+	let c = arc::Computed::new(Box::new({
+		let b = b.clone();
+		move |cx| *b.get(cx) + 1
+	}));
+
+	let runs = Arc::new(Mutex::new(Vec::new()));
+	let reaction = |name: &'static str, read: Box<dyn Fn(&arc::Evaluation) + Send>| {
+		let runs = runs.clone();
+		let reaction = arc::Reaction::new(Box::new(move |cx| {
+			read(cx);
+			runs.lock().unwrap().push(name)
+		}));
+		reaction.update();
+		reaction
+	};
+
+	let _reactions = [
+		reaction(
+			"deep",
+			Box::new({
+				let c = c.clone();
+				move |cx| {
+					let _ = c.get(cx);
+				}
+			}),
+		),
+		reaction(
+			"middle",
+			Box::new({
+				let b = b.clone();
+				move |cx| {
+					let _ = b.get(cx);
+				}
+			}),
+		),
+		reaction(
+			"shallow",
+			Box::new({
+				let a = a.clone();
+				move |cx| {
+					let _ = a.get(cx);
+				}
+			}),
+		),
+		reaction(
+			"first",
+			Box::new({
+				let c = c.clone();
+				move |cx| {
+					let _ = c.get(cx);
+				}
+			}),
+		)
+		.with_priority(1),
+	];
+
+	runs.lock().unwrap().clear();
+	arc::batch(|| a.set(2));
+	assert_eq!(
+		*runs.lock().unwrap(),
+		vec!["first", "shallow", "middle", "deep"]
+	);
+}
+
+fn panic_message(result: std::thread::Result<()>) -> String {
+	let payload = result.unwrap_err();
+	match payload.downcast::<String>() {
+		Ok(message) => *message,
+		Err(payload) => payload.downcast_ref::<&str>().unwrap().to_string(),
+	}
+}
+
+#[test]
+fn reactions_that_do_not_settle_panic() {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+
+	let x = Var::new_with_name("x", 0);
+	let y = Var::new_with_name("y", 0);
+
+	let ping = Reaction::new_with_name(
+		"ping",
+		Box::new({
+			let (x, y) = (x.clone(), y.clone());
+			move |cx| {
+				let x = x.get(cx);
+				if x > 0 {
+					batch(|| y.set(x + 1));
+				}
+			}
+		}),
+	);
+	let pong = Reaction::new_with_name(
+		"pong",
+		Box::new({
+			let (x, y) = (x.clone(), y.clone());
+			move |cx| {
+				let y = y.get(cx);
+				if y > 0 {
+					batch(|| x.set(y + 1));
+				}
+			}
+		}),
+	);
+
+	ping.update();
+	pong.update();
+	let message = panic_message(catch_unwind(AssertUnwindSafe(|| batch(|| x.set(1)))));
+
+	assert!(message.contains("did not settle after 100 rounds"));
+	assert!(message.contains(r#"round 99: ran ["ping"], changed ["y"]"#));
+	assert!(message.contains(r#"round 100: ran ["pong"], changed ["x"]"#));
+}
+
+#[test]
+fn arc_reactions_that_do_not_settle_panic() {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+
+	use observe::arc;
+
+	let runtime = arc::Runtime::new();
+	let x = arc::Var::new_with_name("x", 0);
+	let y = arc::Var::new_with_name("y", 0);
+
+	let reaction = |name, from: &arc::Var<i32>, to: &arc::Var<i32>| {
+		let (from, to, runtime) = (from.clone(), to.clone(), runtime.clone());
+		arc::Reaction::new_with_name_in(
+			&runtime.clone(),
+			name,
+			Box::new(move |cx| {
+				let value = from.get(cx);
+				if value > 0 {
+					runtime.batch(|| to.set(value + 1));
+				}
+			}),
+		)
+	};
+
+	let ping = reaction("ping", &x, &y);
+	let pong = reaction("pong", &y, &x);
+
+	ping.update();
+	pong.update();
+	let message = panic_message(catch_unwind(AssertUnwindSafe(|| {
+		runtime.batch(|| x.set(1))
+	})));
+
+	assert!(message.contains("did not settle after 100 rounds"));
+	assert!(message.contains(r#"round 100: ran ["pong"], changed ["x"]"#));
+}
+
+#[test]
+fn arc_batch_closes_when_it_panics() {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	use std::sync::atomic::{AtomicI32, Ordering};
+	use std::sync::Arc;
+
+	use observe::arc;
+
+	let runtime = arc::Runtime::new();
+	let x = arc::Var::new(0);
+	let seen = Arc::new(AtomicI32::new(0));
+	let reaction = arc::Reaction::new_in(
+		&runtime,
+		Box::new({
+			let (x, seen) = (x.clone(), seen.clone());
+			move |cx| seen.store(x.get(cx), Ordering::Relaxed)
+		}),
+	);
+	reaction.update();
+
+	let result = catch_unwind(AssertUnwindSafe(|| {
+		runtime.batch(|| {
+			x.set(1);
+			panic!("failed");
+		})
+	}));
+
+	assert!(result.is_err());
+	assert!(!runtime.in_batch());
+	runtime.batch(|| x.set(2));
+	assert_eq!(seen.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn reactions_run_again_after_not_settling() {
+	use std::cell::Cell;
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	use std::rc::Rc;
+
+	let x = Var::new(0);
+	let seen = Rc::new(Cell::new(0));
+
+	let ping = Reaction::new(Box::new({
+		let x = x.clone();
+		move |cx| {
+			let value = x.get(cx);
+			if value > 0 {
+				batch(|| x.set(value + 1));
+			}
+		}
+	}));
+	let watch = Reaction::new(Box::new({
+		let (x, seen) = (x.clone(), seen.clone());
+		move |cx| seen.set(x.get(cx))
+	}));
+
+	ping.update();
+	watch.update();
+	assert!(catch_unwind(AssertUnwindSafe(|| batch(|| x.set(1)))).is_err());
+
+	batch(|| x.set(-1));
+	assert_eq!(seen.get(), -1);
+}
+
+#[test]
+fn arc_reactions_run_again_after_not_settling() {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	use std::sync::atomic::{AtomicI32, Ordering};
+	use std::sync::Arc;
+
+	use observe::arc;
+
+	let runtime = arc::Runtime::new();
+	let x = arc::Var::new(0);
+	let seen = Arc::new(AtomicI32::new(0));
+
+	let ping = arc::Reaction::new_in(
+		&runtime,
+		Box::new({
+			let (x, runtime) = (x.clone(), runtime.clone());
+			move |cx| {
+				let value = x.get(cx);
+				if value > 0 {
+					runtime.batch(|| x.set(value + 1));
+				}
+			}
+		}),
+	);
+	let watch = arc::Reaction::new_in(
+		&runtime,
+		Box::new({
+			let (x, seen) = (x.clone(), seen.clone());
+			move |cx| seen.store(x.get(cx), Ordering::Relaxed)
+		}),
+	);
+
+	ping.update();
+	watch.update();
+	assert!(catch_unwind(AssertUnwindSafe(|| runtime.batch(|| x.set(1)))).is_err());
+
+	runtime.batch(|| x.set(-1));
+	assert_eq!(seen.load(Ordering::Relaxed), -1);
+}
+
+#[test]
+fn computed_that_writes_its_dependency() {
+	let count = Var::new_with_name("count", 0);
+	let next = Computed::new_with_name(
+		"next",
+		Box::new({
+			let count = count.clone();
+			move |cx| {
+				let value = count.get(cx);
+				count.set(value + 1);
+				value
+			}
+		}),
+	);
+
+	let warnings = mock::warnings(|| {
+		let _ = next.get_once();
+		let _ = next.get_once();
+	});
+	assert_eq!(
+		warnings,
+		vec!["Var was written while a Computed evaluates var=count computed=next"]
+	);
+}
+
+#[test]
+fn unnamed_computed_writes_are_reported_each() {
+	let writers: Vec<_> = (0..2)
+		.map(|_| {
+			let target = Var::new(0);
+			Computed::new(Box::new(move |_| target.set(1)))
+		})
+		.collect();
+
+	let warnings = mock::warnings(|| {
+		for writer in &writers {
+			writer.get_once();
+		}
+	});
+	assert_eq!(warnings.len(), 2);
+}
+
+#[test]
+fn arc_computed_that_writes_its_dependency() {
+	use observe::arc;
+
+	let count = arc::Var::new_with_name("arc_count", 0);
+	let next = arc::Computed::new_with_name(
+		"arc_next",
+		Box::new({
+			let count = count.clone();
+			move |cx| {
+				let value = count.get(cx);
+				count.set(value + 1);
+				value
+			}
+		}),
+	);
+
+	let warnings = mock::warnings(|| {
+		let _ = next.get_once();
+		let _ = next.get_once();
+	});
+	assert_eq!(
+		warnings,
+		vec!["Var was written while a Computed evaluates var=arc_count computed=arc_next"]
+	);
+}
+
+#[test]
+fn arc_async_writes_are_reported_by_name() {
+	use futures::executor::LocalPool;
+	use futures::FutureExt;
+	use observe::arc;
+	use observe::spawner::LocalPoolSpawner;
+
+	let pool = LocalPool::new();
+	let (id, requests) = (arc::Var::new(1), arc::Var::new_with_name("requests", 0));
+	let user = arc::Async::new_with_name(
+		"user",
+		{
+			let (id, requests) = (id.clone(), requests.clone());
+			move |cx| {
+				requests.update(|count| *count += 1);
+				id.get(cx)
+			}
+		},
+		|&id, _| async move { id }.boxed(),
+	)
+	.with_spawner(LocalPoolSpawner::new(&pool));
+
+	let warnings = mock::warnings(|| {
+		let _ = user.state_once();
+	});
+	assert_eq!(
+		warnings,
+		vec!["Var was written while a Computed evaluates var=requests computed=user"]
+	);
+}
+
+#[test]
+fn reaction_that_writes_its_dependency() {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	let count = Var::new(0);
+	let runs = Rc::new(RefCell::new(Vec::new()));
+
+	let reaction = Reaction::new(Box::new({
+		let (count, runs) = (count.clone(), runs.clone());
+		move |cx| {
+			let value = count.get(cx);
+			runs.borrow_mut().push(value);
+			if value < 3 {
+				batch(|| count.set(value + 1));
+			}
+		}
+	}));
+
+	reaction.update();
+	assert_eq!(*runs.borrow(), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn arc_reaction_that_writes_its_dependency() {
+	use std::sync::{Arc, Mutex};
+
+	use observe::arc;
+
+	let count = arc::Var::new(0);
+	let runs = Arc::new(Mutex::new(Vec::new()));
+
+	let reaction = arc::Reaction::new(Box::new({
+		let (count, runs) = (count.clone(), runs.clone());
+		move |cx| {
+			let value = count.get(cx);
+			runs.lock().unwrap().push(value);
+			if value < 3 {
+				arc::batch(|| count.set(value + 1));
+			}
+		}
+	}));
+
+	reaction.update();
+	assert_eq!(*runs.lock().unwrap(), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn arc_write_during_a_first_read_reaches_the_reader() {
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::sync::{Arc, Mutex};
+
+	use observe::arc;
+
+	let a = arc::Var::new(1);
+	let wrote = Arc::new(AtomicBool::new(false));
+	let b = arc::Computed::new(Box::new({
+		let a = a.clone();
+		move |cx| {
+			let value = a.get(cx);
+			// another thread writes while the computed is read for the first time
+			if !wrote.swap(true, Ordering::SeqCst) {
+				let a = a.clone();
+				std::thread::spawn(move || arc::batch(|| a.set(2)))
+					.join()
+					.unwrap();
+			}
+			value
+		}
+	}));
+
+	let runs = Arc::new(Mutex::new(Vec::new()));
+	let reaction = arc::Reaction::new(Box::new({
+		let (b, runs) = (b.clone(), runs.clone());
+		move |cx| runs.lock().unwrap().push(*b.get(cx))
+	}));
+
+	reaction.update();
+	assert_eq!(*runs.lock().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn arc_graph_under_contention() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{mpsc, Arc};
+	use std::thread;
+	use std::time::Duration;
+
+	use observe::arc;
+
+	const THREADS: usize = 8;
+	const ITERATIONS: usize = 500;
+
+	let vars: Vec<arc::Var<i64>> = (0..4).map(arc::Var::new).collect();
+	let sums: Vec<arc::Computed<i64>> = (0..vars.len())
+		.map(|i| {
+			let (a, b) = (vars[i].clone(), vars[(i + 1) % vars.len()].clone());
+			arc::Computed::new(Box::new(move |cx| a.get(cx) + b.get(cx)))
+		})
+		.collect();
+	let total = arc::Computed::new(Box::new({
+		let sums = sums.clone();
+		move |cx| sums.iter().map(|sum| *sum.get(cx)).sum::<i64>()
+	}));
+
+	let runs = Arc::new(AtomicUsize::new(0));
+	let _reactions: Vec<arc::Reaction> = (0..THREADS)
+		.map(|_| {
+			let (total, runs) = (total.clone(), runs.clone());
+			let reaction = arc::Reaction::new(Box::new(move |cx| {
+				let _ = total.get(cx);
+				runs.fetch_add(1, Ordering::Relaxed);
+			}));
+			reaction.update();
+			reaction
+		})
+		.collect();
+
+	let (done, finished) = mpsc::channel();
+	for thread in 0..THREADS {
+		let (vars, sums, total, done) = (vars.clone(), sums.clone(), total.clone(), done.clone());
+		thread::spawn(move || {
+			for i in 0..ITERATIONS {
+				let var = &vars[(thread + i) % vars.len()];
+				arc::batch(|| var.set((thread * ITERATIONS + i) as i64));
+
+				// overlapping computeds, read in opposite orders
+				if thread % 2 == 0 {
+					sums.iter().for_each(|sum| drop(sum.get_once()));
+				} else {
+					sums.iter().rev().for_each(|sum| drop(sum.get_once()));
+				}
+				drop(total.get_once());
+			}
+			done.send(()).unwrap();
+		});
+	}
+
+	for _ in 0..THREADS {
+		finished
+			.recv_timeout(Duration::from_secs(60))
+			.expect("the graph deadlocked");
+	}
+
+	let values: Vec<i64> = vars.iter().map(|var| var.get_once()).collect();
+	let expected: i64 = (0..values.len())
+		.map(|i| values[i] + values[(i + 1) % values.len()])
+		.sum();
+	assert_eq!(*total.get_once(), expected);
+	assert!(runs.load(Ordering::Relaxed) > THREADS);
+}
+
+#[test]
+fn arc_var_copies_only_shared_values() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use observe::arc;
+
+	static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+	#[derive(Hash)]
+	struct Counted(u32);
+
+	impl Clone for Counted {
+		fn clone(&self) -> Self {
+			CLONES.fetch_add(1, Ordering::Relaxed);
+			Counted(self.0)
+		}
+	}
+
+	let var = arc::Var::new(Counted(0));
+	for _ in 0..3 {
+		arc::batch(|| var.update(|value| value.0 += 1));
+	}
+	assert_eq!(CLONES.load(Ordering::Relaxed), 0);
+
+	let snapshot = var.get_arc_once();
+	assert_eq!((snapshot.0, var.get_arc_once().0), (3, 3));
+	assert_eq!(CLONES.load(Ordering::Relaxed), 0);
+
+	arc::batch(|| var.update(|value| value.0 += 1));
+	arc::batch(|| var.update(|value| value.0 += 1));
+	assert_eq!((snapshot.0, var.get_ref_once().0), (3, 5));
+	assert_eq!(CLONES.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn arc_var_snapshots_are_not_copies() {
+	use observe::arc;
+
+	#[derive(Hash)]
+	struct Unique(u32);
+
+	let var = arc::Var::new(Unique(1));
+	let snapshot = var.get_arc_once();
+	arc::batch(|| var.set(Unique(2)));
+
+	assert_eq!((snapshot.0, var.get_arc_once().0), (1, 2));
+	assert!(std::sync::Arc::ptr_eq(
+		&var.get_arc_once(),
+		&var.get_arc_once()
+	));
+}
+
+#[test]
+fn arc_computed_values_do_not_block_evaluations() {
+	use observe::arc;
+
+	let a = arc::Var::new(1);
+	let double = a.map(|a| a * 2);
+
+	let before = double.get_once();
+	arc::batch(|| a.set(2));
+
+	assert_eq!((*before, *double.get_once()), (2, 4));
+}
+
+#[test]
+fn arc_snapshots_outlive_writes() {
+	use std::sync::{Arc, Mutex};
+
+	use observe::arc;
+
+	let name = arc::Var::new(String::from("a"));
+	let upper = name.map(|name| name.to_uppercase());
+	let seen = Arc::new(Mutex::new(Vec::new()));
+
+	let reaction = arc::Reaction::new(Box::new({
+		let (upper, seen) = (upper.clone(), seen.clone());
+		move |cx| seen.lock().unwrap().push(upper.get_arc(cx))
+	}));
+	reaction.update();
+
+	let before = (name.get_arc_once(), upper.get_arc_once());
+	arc::batch(|| name.set(String::from("b")));
+
+	assert_eq!((before.0.as_str(), before.1.as_str()), ("a", "A"));
+	assert_eq!(*name.get_arc_once(), "b");
+	assert_eq!(*upper.get_arc_once(), "B");
+	assert_eq!(
+		*seen.lock().unwrap(),
+		vec![Arc::new("A".into()), Arc::new("B".into())]
+	);
+}
@@ -7,15 +7,65 @@ pub trait Spy {
 	fn trigger(&self, value: u64);
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct SharedMock(Arc<Mutex<MockSpy>>);
 
 impl SharedMock {
 	pub fn new() -> SharedMock {
-		SharedMock(Arc::new(Mutex::new(MockSpy::new())))
+		SharedMock::default()
 	}
 
 	pub fn get<'a>(&'a self) -> MutexGuard<'a, MockSpy> {
-		return self.0.lock().unwrap();
+		self.0.lock().unwrap()
+	}
+}
+
+/// Warnings logged on this thread while `func` runs, as the message
+/// followed by the string fields, like `message var=name`.
+pub fn warnings(func: impl FnOnce()) -> Vec<String> {
+	let recorder = Warnings::default();
+	tracing::subscriber::with_default(recorder.clone(), func);
+	let messages = std::mem::take(&mut *recorder.0.lock().unwrap());
+	messages
+}
+
+#[derive(Clone, Default)]
+struct Warnings(Arc<Mutex<Vec<String>>>);
+
+impl tracing::Subscriber for Warnings {
+	fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+		*metadata.level() == tracing::Level::WARN
+	}
+
+	fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+		tracing::span::Id::from_u64(1)
+	}
+
+	fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+	fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+	fn event(&self, event: &tracing::Event<'_>) {
+		let mut message = Message(String::new());
+		event.record(&mut message);
+		self.0.lock().unwrap().push(message.0);
+	}
+
+	fn enter(&self, _: &tracing::span::Id) {}
+
+	fn exit(&self, _: &tracing::span::Id) {}
+}
+
+struct Message(String);
+
+impl tracing::field::Visit for Message {
+	fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+		self.0 = format!("{} {}={value}", self.0, field.name());
+	}
+
+	fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+		if field.name() == "message" {
+			self.0 = format!("{value:?}{}", self.0);
+		}
 	}
 }